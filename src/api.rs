@@ -1,17 +1,25 @@
+use crate::metastore::MetastoreBackend;
 use anyhow::Result;
+use async_trait::async_trait;
 use reqwest_middleware::{
     reqwest::{self},
     ClientBuilder, ClientWithMiddleware,
 };
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde::{de::DeserializeOwned, Deserialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 
-
+/// A catalog as listed by the API.
 #[derive(Debug, Deserialize)]
 pub struct Catalog {
     pub name: String,
+    pub owner: Option<String>,
+    pub comment: Option<String>,
+    pub catalog_type: Option<String>,
+    pub provider_name: Option<String>,
+    pub share_name: Option<String>,
+    pub connection_name: Option<String>,
 }
 
 
@@ -25,6 +33,9 @@ pub struct GetCatalogResponse {
 pub struct Schema {
     pub name: String,
     pub catalog_name: String,
+    pub owner: Option<String>,
+    pub comment: Option<String>,
+    pub updated_at: Option<i64>,
 }
 #[derive(Deserialize, Debug)]
 pub struct GetSchemaResponse {
@@ -34,41 +45,191 @@ pub struct GetSchemaResponse {
 #[derive(Deserialize, Debug)]
 pub struct Table {
     pub name: String,
+    pub table_id: Option<String>,
     pub catalog_name: String,
     pub schema_name: String,
     pub table_type: String,
     pub data_source_format: Option<String>,
     pub updated_at: i64,
     pub updated_by: String,
+    pub owner: Option<String>,
+    pub comment: Option<String>,
+    pub view_definition: Option<String>,
+    #[serde(default)]
+    pub columns: Vec<Column>,
+    #[serde(default)]
     pub properties: HashMap<String, String>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct Column {
+    pub name: String,
+    pub type_text: String,
+    pub nullable: Option<bool>,
+    pub position: i64,
+    pub comment: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct GetTableResponse {
     tables: Option<Vec<Table>>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct TableSummary {
+    /// `catalog.schema.table`.
+    pub full_name: String,
+    pub table_type: String,
+    pub table_id: Option<String>,
+    pub data_source_format: Option<String>,
+    pub updated_at: i64,
+    pub updated_by: Option<String>,
+    pub owner: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListTableSummariesResponse {
+    tables: Option<Vec<TableSummary>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Volume {
+    pub name: String,
+    pub catalog_name: String,
+    pub schema_name: String,
+    pub volume_type: String,
+    pub storage_location: Option<String>,
+    pub updated_at: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetVolumeResponse {
+    volumes: Option<Vec<Volume>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FunctionParameterInfo {
+    pub name: String,
+    pub type_text: String,
+    pub position: i64,
+    pub parameter_default: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FunctionParameterInfos {
+    #[serde(default)]
+    pub parameters: Vec<FunctionParameterInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Function {
+    pub name: String,
+    pub catalog_name: String,
+    pub schema_name: String,
+    pub data_type: Option<String>,
+    pub full_data_type: Option<String>,
+    pub input_params: Option<FunctionParameterInfos>,
+    pub return_params: Option<FunctionParameterInfos>,
+    pub routine_body: Option<String>,
+    pub routine_definition: Option<String>,
+    pub external_language: Option<String>,
+    pub is_deterministic: Option<bool>,
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetFunctionResponse {
+    functions: Option<Vec<Function>>,
+}
 
+#[derive(Deserialize, Debug)]
+pub struct PrivilegeAssignment {
+    pub principal: String,
+    pub privileges: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPermissionsResponse {
+    privilege_assignments: Option<Vec<PrivilegeAssignment>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TagAssignment {
+    pub tag_key: String,
+    pub tag_value: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetTagAssignmentsResponse {
+    tag_assignments: Option<Vec<TagAssignment>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatementError {
+    pub error_code: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatementStatus {
+    pub state: String,
+    pub error: Option<StatementError>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatementResponse {
+    pub statement_id: String,
+    pub status: StatementStatus,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DirectoryEntry {
+    pub path: String,
+    #[serde(default)]
+    pub is_directory: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListDirectoryResponse {
+    contents: Option<Vec<DirectoryEntry>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CurrentUser {
+    #[serde(rename = "userName")]
+    pub user_name: String,
+}
+
+
+/// Client for the Databricks REST API, authenticated with a personal access token.
+/// Transient failures are retried.
 #[derive(Clone)]
 pub struct Client {
-    host: String,
+    base_url: String,
     client: ClientWithMiddleware,
     pat: String,
 }
 
 impl Client {
+    /// `host` is a workspace hostname, which is reached over HTTPS, or a URL with a scheme.
     pub fn new(host: &str, pat: &str) -> Self {
         let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
         let client = ClientBuilder::new(reqwest::Client::new())
             .with(RetryTransientMiddleware::new_with_policy(retry_policy))
             .build();
 
+        let base_url = match host.contains("://") {
+            true => host.trim_end_matches('/').to_string(),
+            false => format!("https://{}", host),
+        };
         Self {
-            host: host.to_string(),
-            client: client,
+            base_url,
+            client,
             pat: pat.to_string(),
         }
     }
+
     async fn get<T>(&self, endpoint: &str, query: &[(&str, &str)]) -> Result<Vec<T>>
     where
         T: DeserializeOwned,
@@ -77,7 +238,7 @@ impl Client {
         let mut next_page: Option<String> = None;
         let req_template = self
             .client
-            .get(format!("https://{}/{}", self.host, endpoint))
+            .get(format!("{}/{}", self.base_url, endpoint))
             .bearer_auth(&self.pat)
             .query(query);
         loop {
@@ -91,14 +252,36 @@ impl Client {
             data.push(response_parsed);
 
             let response_npt: Value = serde_json::from_str(&npt)?;
-            if let Some(token) = response_npt.get("next_page_token") {
-                next_page = Some(token.to_string());
-            } else {
-                break;
+            match response_npt.get("next_page_token").and_then(Value::as_str) {
+                Some(token) if !token.is_empty() => next_page = Some(token.to_string()),
+                _ => break,
             }
         }
         Ok(data)
     }
+
+    async fn post<T>(&self, endpoint: &str, body: &Value) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let response = self
+            .client
+            .post(format!("{}/{}", self.base_url, endpoint))
+            .bearer_auth(&self.pat)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+
+    async fn list_directory(&self, path: &str) -> Result<Vec<DirectoryEntry>> {
+        let listing = self
+            .get::<ListDirectoryResponse>(&format!("api/2.0/fs/directories{}", path), &[])
+            .await?;
+        Ok(listing.into_iter().flat_map(|l| l.contents.unwrap_or_default()).collect())
+    }
 }
 
 pub struct FetchAllCatalogs {}
@@ -110,44 +293,259 @@ pub struct FetchCatalog {
 pub struct FetchSchema {
     pub catalog_name: String,
     pub schema_name: String,
+    /// As listed with the schema, compared against the cached schema.
+    pub updated_at: Option<i64>,
+}
+
+pub struct FetchVolumes {
+    pub catalog_name: String,
+    pub schema_name: String,
+}
+
+pub struct FetchFunctions {
+    pub catalog_name: String,
+    pub schema_name: String,
+}
+
+pub struct FetchTable {
+    pub catalog_name: String,
+    pub schema_name: String,
+    pub table_name: String,
+}
+
+pub struct FetchPermissions {
+    pub catalog_name: String,
+    pub schema_name: Option<String>,
+    pub table_name: Option<String>,
 }
 
+pub struct FetchTags {
+    pub catalog_name: String,
+    pub schema_name: Option<String>,
+    pub table_name: Option<String>,
+    pub column_name: Option<String>,
+}
 
+#[allow(clippy::enum_variant_names)]
 pub enum FetchJob {
     FetchAllCatalogs(FetchAllCatalogs),
     FetchCatalog(FetchCatalog),
     FetchSchema(FetchSchema),
+    FetchTable(FetchTable),
+    FetchVolumes(FetchVolumes),
+    FetchFunctions(FetchFunctions),
+    FetchPermissions(FetchPermissions),
+    FetchTags(FetchTags),
+}
+
+impl FetchPermissions {
+    /// Securable type and full name, as in `/permissions/{type}/{name}`.
+    pub fn securable(&self) -> (&'static str, String) {
+        match (&self.schema_name, &self.table_name) {
+            (Some(schema), Some(table)) => ("table", format!("{}.{}.{}", self.catalog_name, schema, table)),
+            (Some(schema), None) => ("schema", format!("{}.{}", self.catalog_name, schema)),
+            _ => ("catalog", self.catalog_name.clone()),
+        }
+    }
 }
 
-impl FetchAllCatalogs {
-    pub async fn get_children(self, client: &Client) -> Result<Vec<Catalog>> {
-        let catalogs = client
-            .get::<GetCatalogResponse>("api/2.1/unity-catalog/catalogs", &[]).await?;
-        Ok(catalogs.into_iter().map(|c| c.catalogs).flatten().collect())
+impl FetchTags {
+    /// Entity type and full name, as in `/entity-tag-assignments/{type}/{name}/tags`.
+    pub fn entity(&self) -> (&'static str, String) {
+        match (&self.schema_name, &self.table_name, &self.column_name) {
+            (Some(schema), Some(table), Some(column)) => {
+                ("columns", format!("{}.{}.{}.{}", self.catalog_name, schema, table, column))
+            }
+            (Some(schema), Some(table), None) => ("tables", format!("{}.{}.{}", self.catalog_name, schema, table)),
+            (Some(schema), None, _) => ("schemas", format!("{}.{}", self.catalog_name, schema)),
+            _ => ("catalogs", self.catalog_name.clone()),
+        }
     }
 }
 
-impl FetchCatalog {
-    pub async fn get_children(&self, client: &Client) -> Result<Vec<Schema>> {
-        let schemas = client
-            .get::<GetSchemaResponse>(
-                "api/2.1/unity-catalog/schemas",
-                &[("catalog_name", &self.catalog_name)],
+#[async_trait]
+impl MetastoreBackend for Client {
+    async fn list_catalogs(&self) -> Result<Vec<Catalog>> {
+        let catalogs = self.get::<GetCatalogResponse>("api/2.1/unity-catalog/catalogs", &[]).await?;
+        Ok(catalogs.into_iter().flat_map(|c| c.catalogs).collect())
+    }
+
+    async fn get_catalog(&self, catalog_name: &str) -> Result<Catalog> {
+        let catalog = self
+            .get::<Catalog>(&format!("api/2.1/unity-catalog/catalogs/{}", catalog_name), &[])
+            .await?;
+        catalog
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("catalog {} not found", catalog_name))
+    }
+
+    async fn list_table_summaries(&self, catalog_name: &str) -> Result<Option<Vec<TableSummary>>> {
+        let summaries = self
+            .get::<ListTableSummariesResponse>(
+                "api/2.1/unity-catalog/table-summaries",
+                &[("catalog_name", catalog_name)],
             )
             .await?;
-        Ok(schemas.into_iter().map(|s| s.schemas).flatten().collect())
+        Ok(Some(summaries.into_iter().flat_map(|s| s.tables.unwrap_or_default()).collect()))
     }
-}
 
-impl FetchSchema {
-    pub async fn get_children(&self, client: &Client) -> Result<Vec<Table>> {
-        let tables = client
+    async fn list_schemas(&self, catalog_name: &str) -> Result<Vec<Schema>> {
+        let schemas = self
+            .get::<GetSchemaResponse>("api/2.1/unity-catalog/schemas", &[("catalog_name", catalog_name)])
+            .await?;
+        Ok(schemas.into_iter().flat_map(|s| s.schemas).collect())
+    }
+
+    async fn list_tables(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Table>> {
+        let tables = self
             .get::<GetTableResponse>(
                 "api/2.1/unity-catalog/tables",
-                &[("catalog_name", &self.catalog_name), ("schema_name", &self.schema_name)],
+                &[("catalog_name", catalog_name), ("schema_name", schema_name)],
             )
             .await?;
-        Ok(tables.into_iter().map(|t| t.tables.unwrap_or_default()).flatten().collect())
+        Ok(tables.into_iter().flat_map(|t| t.tables.unwrap_or_default()).collect())
+    }
+
+    async fn list_tables_brief(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Table>> {
+        let tables = self
+            .get::<GetTableResponse>(
+                "api/2.1/unity-catalog/tables",
+                &[
+                    ("catalog_name", catalog_name),
+                    ("schema_name", schema_name),
+                    ("omit_columns", "true"),
+                    ("omit_properties", "true"),
+                ],
+            )
+            .await?;
+        Ok(tables.into_iter().flat_map(|t| t.tables.unwrap_or_default()).collect())
+    }
+
+    async fn get_table(&self, catalog_name: &str, schema_name: &str, table_name: &str) -> Result<Table> {
+        let table = self
+            .get::<Table>(
+                &format!("api/2.1/unity-catalog/tables/{}.{}.{}", catalog_name, schema_name, table_name),
+                &[],
+            )
+            .await?;
+        table
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("table {}.{}.{} not found", catalog_name, schema_name, table_name))
+    }
+
+    async fn list_volumes(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Volume>> {
+        let volumes = self
+            .get::<GetVolumeResponse>(
+                "api/2.1/unity-catalog/volumes",
+                &[("catalog_name", catalog_name), ("schema_name", schema_name)],
+            )
+            .await?;
+        Ok(volumes.into_iter().flat_map(|v| v.volumes.unwrap_or_default()).collect())
+    }
+
+    async fn list_functions(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Function>> {
+        let functions = self
+            .get::<GetFunctionResponse>(
+                "api/2.1/unity-catalog/functions",
+                &[("catalog_name", catalog_name), ("schema_name", schema_name)],
+            )
+            .await?;
+        Ok(functions.into_iter().flat_map(|f| f.functions.unwrap_or_default()).collect())
+    }
+
+    async fn list_permissions(&self, securable_type: &str, full_name: &str) -> Result<Vec<PrivilegeAssignment>> {
+        let permissions = self
+            .get::<GetPermissionsResponse>(
+                &format!("api/2.1/unity-catalog/permissions/{}/{}", securable_type, full_name),
+                &[],
+            )
+            .await?;
+        Ok(permissions.into_iter().flat_map(|p| p.privilege_assignments.unwrap_or_default()).collect())
+    }
+
+    async fn list_tags(&self, entity_type: &str, entity_name: &str) -> Result<Vec<TagAssignment>> {
+        let tags = self
+            .get::<GetTagAssignmentsResponse>(
+                &format!("api/2.1/entity-tag-assignments/{}/{}/tags", entity_type, entity_name),
+                &[],
+            )
+            .await?;
+        Ok(tags.into_iter().flat_map(|t| t.tag_assignments.unwrap_or_default()).collect())
+    }
+
+    async fn current_user(&self) -> Result<String> {
+        let user = self.get::<CurrentUser>("api/2.0/preview/scim/v2/Me", &[]).await?;
+        user.into_iter()
+            .next()
+            .map(|u| u.user_name)
+            .ok_or_else(|| anyhow::anyhow!("could not determine current user"))
+    }
+
+    async fn start_statement(&self, warehouse_id: &str, statement: &str) -> Result<StatementResponse> {
+        self.post(
+            "api/2.0/sql/statements",
+            &json!({
+                "warehouse_id": warehouse_id,
+                "statement": statement,
+                "wait_timeout": "10s",
+                "on_wait_timeout": "CONTINUE",
+            }),
+        )
+        .await
     }
-}
 
+    async fn statement_state(&self, statement_id: &str) -> Result<StatementResponse> {
+        self.get::<StatementResponse>(&format!("api/2.0/sql/statements/{}", statement_id), &[])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("empty statement status response"))
+    }
+
+    async fn cancel_statement(&self, statement_id: &str) -> Result<()> {
+        self.client
+            .post(format!("{}/api/2.0/sql/statements/{}/cancel", self.base_url, statement_id))
+            .bearer_auth(&self.pat)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn copy_directory(&self, source: &str, target: &str) -> Result<usize> {
+        let source = source.trim_end_matches('/');
+        let target = target.trim_end_matches('/');
+        let mut pending = vec![source.to_string()];
+        let mut copied = 0;
+        while let Some(directory) = pending.pop() {
+            for entry in self.list_directory(&directory).await? {
+                if entry.is_directory {
+                    pending.push(entry.path);
+                    continue;
+                }
+                let destination = format!("{}{}", target, &entry.path[source.len()..]);
+                let content = self
+                    .client
+                    .get(format!("{}/api/2.0/fs/files{}", self.base_url, entry.path))
+                    .bearer_auth(&self.pat)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?;
+                self.client
+                    .put(format!("{}/api/2.0/fs/files{}", self.base_url, destination))
+                    .bearer_auth(&self.pat)
+                    .query(&[("overwrite", "true")])
+                    .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                    .body(content)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+}
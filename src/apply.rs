@@ -0,0 +1,228 @@
+use crate::metastore::MetastoreBackend;
+use crate::querygen::Query;
+use anyhow::{anyhow, bail, Result};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// How often a running statement is polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What happened to one flattened query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Part of a plan that was not applied.
+    Planned,
+    Succeeded,
+    Failed,
+    /// Stopped while running, because applying was cancelled or ran past its deadline.
+    Cancelled,
+    /// Nothing to run, such as a table that is intentionally not synced.
+    Skipped,
+    /// Not attempted because an earlier query of the same plan failed, or applying was stopped.
+    NotRun,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Status::Planned => "planned",
+            Status::Succeeded => "succeeded",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+            Status::Skipped => "skipped",
+            Status::NotRun => "not run",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outcome {
+    pub status: Status,
+    /// Time spent on the statements and file copies of the query, when they ran.
+    pub duration: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    pub(crate) fn new(status: Status) -> Self {
+        Self {
+            status,
+            duration: None,
+            error: None,
+        }
+    }
+}
+
+/// Parses a duration such as `90s`, `30m` or `2h`.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let split = duration.len() - duration.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (amount, unit) = duration.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| format!("invalid duration {}", duration))?;
+    match unit {
+        "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        "h" => Ok(Duration::from_secs(amount * 3600)),
+        _ => Err(format!("invalid duration {}, expected a number followed by s, m or h", duration)),
+    }
+}
+
+/// Bounds on how long applying may take, and a way to stop it early.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Longest a single statement may run. It is then cancelled and fails.
+    pub statement_timeout: Option<Duration>,
+    /// When applying has to be done. Running statements are cancelled then and
+    /// nothing new starts.
+    pub deadline: Option<Instant>,
+    /// Stops applying once it becomes true, as on Ctrl-C. Running statements are
+    /// cancelled and nothing new starts.
+    pub cancel: Option<watch::Receiver<bool>>,
+}
+
+impl Limits {
+    /// Whether applying was cancelled or ran past its deadline.
+    pub fn stopped(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| *cancel.borrow())
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Why work that was still running was given up, other than its own timeout.
+#[derive(Debug)]
+struct Stopped(&'static str);
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Resolves when `deadline` passes, never without one.
+async fn until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves once `cancel` is set, never without one.
+async fn cancelled(cancel: &mut Option<watch::Receiver<bool>>) {
+    if let Some(cancel) = cancel {
+        if cancel.wait_for(|cancelled| *cancelled).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Runs flattened queries in order on a SQL warehouse, stopping at the first failure.
+/// Returns one outcome per query, which are also passed to `on_outcome` with the
+/// index of their query as soon as they are known. Once `limits` stop applying,
+/// the running query is cancelled and the rest are not run.
+pub async fn apply(
+    backend: &dyn MetastoreBackend,
+    warehouse_id: &str,
+    queries: &[Query],
+    limits: &Limits,
+    mut on_outcome: impl FnMut(usize, &Outcome),
+) -> Vec<Outcome> {
+    let mut outcomes = Vec::with_capacity(queries.len());
+    let mut failed = false;
+    for (index, query) in queries.iter().enumerate() {
+        let outcome = if failed || limits.stopped() {
+            Outcome::new(Status::NotRun)
+        } else {
+            if let Some(note) = &query.note {
+                info!("{}", note);
+            }
+            if query.statements.is_empty() && query.copy_files.is_none() {
+                Outcome::new(Status::Skipped)
+            } else {
+                let started = Instant::now();
+                let result = run(backend, warehouse_id, query, limits).await;
+                let mut outcome = Outcome {
+                    duration: Some(started.elapsed()),
+                    ..Outcome::new(Status::Succeeded)
+                };
+                if let Err(e) = result {
+                    error!("{:#}", e);
+                    outcome.status = match e.is::<Stopped>() {
+                        true => Status::Cancelled,
+                        false => Status::Failed,
+                    };
+                    outcome.error = Some(format!("{:#}", e));
+                    failed = true;
+                }
+                outcome
+            }
+        };
+        on_outcome(index, &outcome);
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+async fn run(backend: &dyn MetastoreBackend, warehouse_id: &str, query: &Query, limits: &Limits) -> Result<()> {
+    for statement in &query.statements {
+        info!("Running {}", statement);
+        execute(backend, warehouse_id, statement, limits).await?;
+    }
+    if let Some((source, target)) = &query.copy_files {
+        let mut cancel = limits.cancel.clone();
+        let copied = tokio::select! {
+            copied = backend.copy_directory(source, target) => copied?,
+            _ = until(limits.deadline) => return Err(Stopped("the deadline passed").into()),
+            _ = cancelled(&mut cancel) => return Err(Stopped("cancelled").into()),
+        };
+        info!("Copied {} files from {} to {}", copied, source, target);
+    }
+    Ok(())
+}
+
+/// Runs one statement and waits until it reaches a terminal state. A statement
+/// still running at its timeout, the deadline or cancellation is cancelled on the
+/// warehouse, so it does not keep going after we stopped waiting for it.
+async fn execute(backend: &dyn MetastoreBackend, warehouse_id: &str, statement: &str, limits: &Limits) -> Result<()> {
+    let timeout = limits.statement_timeout.map(|timeout| (timeout, Instant::now() + timeout));
+    let mut response = backend.start_statement(warehouse_id, statement).await?;
+    let mut cancel = limits.cancel.clone();
+    loop {
+        match response.status.state.as_str() {
+            "SUCCEEDED" => return Ok(()),
+            "PENDING" | "RUNNING" => {}
+            state => {
+                let message = response
+                    .status
+                    .error
+                    .and_then(|e| e.message.or(e.error_code))
+                    .unwrap_or_default();
+                bail!("statement {} {}: {}", response.statement_id, state, message);
+            }
+        }
+        let stopped: Option<anyhow::Error> = tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => None,
+            _ = until(timeout.map(|(_, at)| at)) => Some(anyhow!(
+                "statement {} timed out after {}s",
+                response.statement_id,
+                timeout.unwrap().0.as_secs()
+            )),
+            _ = until(limits.deadline) => Some(Stopped("the deadline passed").into()),
+            _ = cancelled(&mut cancel) => Some(Stopped("cancelled").into()),
+        };
+        if let Some(e) = stopped {
+            info!("Cancelling statement {}", response.statement_id);
+            if let Err(cancel_error) = backend.cancel_statement(&response.statement_id).await {
+                warn!("Could not cancel statement {}: {:#}", response.statement_id, cancel_error);
+            }
+            return Err(e);
+        }
+        response = backend.statement_state(&response.statement_id).await?;
+    }
+}
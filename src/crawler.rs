@@ -0,0 +1,890 @@
+use crate::api::{self, FetchJob};
+use crate::metastore::MetastoreBackend;
+use crate::schema::SyncEntry;
+use crate::unitycatalog::{
+    Catalog, CatalogType, Column, DiffOptions, Function, FunctionParameter, FunctionReturns, Grants, Permissions, Schema, Table,
+    TagAssignments, Tags, UnityCatalog, UnityCatalogElement, Volume,
+};
+use anyhow::Result;
+use chrono::DateTime;
+use futures::stream::StreamExt;
+use log::{debug, warn};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// How much of each table the first pass of a crawl fetches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CrawlMode {
+    /// List every schema with full table details.
+    Full,
+    /// Take the tables of each catalog from its table summaries, without columns and
+    /// properties. Details are fetched afterwards, only for tables the diff reads them from.
+    Fast,
+}
+
+/// File name of the crawl cache of a workspace. The scheme is left out and
+/// characters other than letters, digits, `.` and `-` become `_`, so
+/// `http://127.0.0.1:8080` is cached in `127.0.0.1_8080.json`.
+pub fn cache_file_name(host: &str) -> String {
+    let host = host.split_once("://").map_or(host, |(_, rest)| rest).trim_end_matches('/');
+    let name: String = host
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("{}.json", name)
+}
+
+/// Crawls catalogs with an on-disk cache of the previous crawl.
+#[derive(Clone)]
+pub struct Crawler {
+    pub backend: Arc<dyn MetastoreBackend>,
+    /// Maximum number of concurrent API requests.
+    pub concurrency: usize,
+    pub mode: CrawlMode,
+    /// JSON file of the previous crawl, see [`UnityCatalog::save`].
+    pub cache_path: PathBuf,
+    /// Crawl everything from scratch. The cache is still refreshed afterwards.
+    pub no_cache: bool,
+}
+
+impl Crawler {
+    /// Fetches the given catalogs and everything below them that `entries` sync.
+    /// Fails when any request fails, since a partial crawl would plan changes for
+    /// objects that were only missed. Catalogs that do not exist are left out.
+    pub async fn crawl(&self, catalogs: &HashSet<String>, entries: &[SyncEntry]) -> Result<UnityCatalog> {
+        let mut unity_catalog = UnityCatalog::new();
+        if catalogs.is_empty() {
+            return Ok(unity_catalog);
+        }
+        let cache = self.load_cache();
+
+        // Create a queue for pending jobs
+        let mut job_queue: VecDeque<FetchJob> = VecDeque::new();
+        for c in catalogs {
+            job_queue.push_back(FetchJob::FetchCatalog(api::FetchCatalog {
+                catalog_name: c.clone(),
+            }));
+        }
+        let reusable = match self.no_cache {
+            true => &UnityCatalog::new(),
+            false => &cache,
+        };
+        self.run_jobs(&mut unity_catalog, job_queue, &self.options(entries), reusable).await?;
+        self.save_cache(cache, &unity_catalog);
+        Ok(unity_catalog)
+    }
+
+    /// Fetches columns and properties of the given brief tables, as `catalog.schema.table` parts.
+    pub async fn fetch_details(
+        &self,
+        unity_catalog: &mut UnityCatalog,
+        tables: BTreeSet<(String, String, String)>,
+        entries: &[SyncEntry],
+    ) -> Result<()> {
+        if tables.is_empty() {
+            return Ok(());
+        }
+        debug!("Fetching details of {} tables", tables.len());
+        let job_queue = tables
+            .into_iter()
+            .map(|(catalog_name, schema_name, table_name)| {
+                FetchJob::FetchTable(api::FetchTable {
+                    catalog_name,
+                    schema_name,
+                    table_name,
+                })
+            })
+            .collect();
+        self.run_jobs(unity_catalog, job_queue, &self.options(entries), &UnityCatalog::new()).await?;
+        self.save_cache(self.load_cache(), unity_catalog);
+        Ok(())
+    }
+
+    fn options(&self, entries: &[SyncEntry]) -> CrawlOptions {
+        CrawlOptions {
+            brief: self.mode == CrawlMode::Fast,
+            ..crawl_options(entries)
+        }
+    }
+
+    fn load_cache(&self) -> UnityCatalog {
+        UnityCatalog::load(&self.cache_path).unwrap_or_else(|e| {
+            debug!("Not using crawl cache {}: {}", self.cache_path.display(), e);
+            UnityCatalog::new()
+        })
+    }
+
+    fn save_cache(&self, mut cache: UnityCatalog, crawled: &UnityCatalog) {
+        cache
+            .catalogs
+            .extend(crawled.catalogs.iter().map(|(name, catalog)| (name.clone(), catalog.clone())));
+        let saved = match self.cache_path.parent() {
+            Some(parent) => std::fs::create_dir_all(parent).map_err(anyhow::Error::from),
+            None => Ok(()),
+        }
+        .and_then(|_| cache.save(&self.cache_path));
+        if let Err(e) = saved {
+            warn!("Could not write crawl cache {}: {}", self.cache_path.display(), e);
+        }
+    }
+
+    /// Runs `jobs` and the jobs they spawn, inserting what they fetch into `unity_catalog`.
+    /// Tables of schemas that did not change since `cache` was crawled are reused,
+    /// without listing them when the schema's own `updated_at` did not move.
+    /// After the first failed job, no new jobs start and its error is returned once
+    /// the running ones finish.
+    async fn run_jobs(
+        &self,
+        unity_catalog: &mut UnityCatalog,
+        mut job_queue: VecDeque<FetchJob>,
+        options: &CrawlOptions,
+        cache: &UnityCatalog,
+    ) -> Result<()> {
+        let concurrency = self.concurrency;
+        let mut active_futures = futures::stream::FuturesUnordered::new();
+
+        while active_futures.len() < concurrency && !job_queue.is_empty() {
+            if let Some(job) = job_queue.pop_front() {
+                active_futures.push(self.job_future(job, options, cache));
+            }
+        }
+
+        let mut failure = None;
+        while !active_futures.is_empty() {
+            if let Some(result) = active_futures.next().await {
+                match result {
+                    Ok((new_jobs, uc_elements)) => {
+                        uc_elements.into_iter().for_each(|element| {
+                            unity_catalog.insert_assume_ordered(element);
+                        });
+                        if failure.is_none() {
+                            job_queue.extend(new_jobs);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Crawl request failed: {:#}", e);
+                        job_queue.clear();
+                        failure.get_or_insert(e);
+                    }
+                }
+                while active_futures.len() < concurrency && !job_queue.is_empty() {
+                    if let Some(job) = job_queue.pop_front() {
+                        active_futures.push(self.job_future(job, options, cache));
+                    }
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(e.context("crawl failed")),
+            None => Ok(()),
+        }
+    }
+
+    fn job_future<'a>(&'a self, job: FetchJob, options: &'a CrawlOptions, cache: &'a UnityCatalog) -> JobFuture<'a> {
+        Box::pin(self.process_job(job, options, cache))
+    }
+
+    /// Fetches what one job asks for. Returns the jobs for the objects below it and
+    /// the elements to insert.
+    async fn process_job(
+        &self,
+        job: FetchJob,
+        options: &CrawlOptions,
+        cache: &UnityCatalog,
+    ) -> Result<(Vec<FetchJob>, Vec<UnityCatalogElement>)> {
+        let backend = self.backend.as_ref();
+        let mut new_jobs = Vec::new();
+        let mut new_elements = Vec::new();
+
+        match job {
+            FetchJob::FetchAllCatalogs(_) => {
+                debug!("Fetching all catalogs");
+                let children = backend.list_catalogs().await?;
+                new_jobs.extend(children.iter().map(|catalog| FetchJob::FetchCatalog(api::FetchCatalog {
+                    catalog_name: catalog.name.clone(),
+                })));
+                new_elements.extend(
+                    children
+                        .into_iter()
+                        .map(|catalog| UnityCatalogElement::Catalog(catalog_from_api(catalog))),
+                );
+            }
+            FetchJob::FetchCatalog(c) => {
+                debug!("Fetching catalog {}", c.catalog_name);
+                match backend.get_catalog(&c.catalog_name).await {
+                    Ok(details) => new_elements.push(UnityCatalogElement::Catalog(catalog_from_api(details))),
+                    // A pinned catalog that does not exist yet is planned as created from scratch.
+                    Err(e) if is_not_found(&e) => {
+                        debug!("Catalog {} does not exist", c.catalog_name);
+                        return Ok((new_jobs, new_elements));
+                    }
+                    Err(e) => return Err(e),
+                }
+                // Queued only after the catalog element above, which they are inserted into.
+                if options.permission_catalogs.contains(&c.catalog_name) {
+                    new_jobs.push(permissions_job(&c.catalog_name, None, None));
+                }
+                if options.tag_catalogs.contains(&c.catalog_name) {
+                    new_jobs.push(tags_job(&c.catalog_name, None, None, None));
+                }
+                // In brief mode, the tables of the whole catalog come from its table
+                // summaries, so no schema is listed for its tables.
+                let summaries = match options.brief {
+                    true => backend.list_table_summaries(&c.catalog_name).await?,
+                    false => None,
+                };
+                let children = backend.list_schemas(&c.catalog_name).await?;
+                if summaries.is_none() {
+                    new_jobs.extend(children.iter().map(|schema| {
+                        FetchJob::FetchSchema(api::FetchSchema {
+                            catalog_name: schema.catalog_name.clone(),
+                            schema_name: schema.name.clone(),
+                            updated_at: schema.updated_at,
+                        })
+                    }));
+                }
+                if options.permission_catalogs.contains(&c.catalog_name) {
+                    new_jobs.extend(
+                        children
+                            .iter()
+                            .map(|schema| permissions_job(&schema.catalog_name, Some(&schema.name), None)),
+                    );
+                }
+                if options.volume_catalogs.contains(&c.catalog_name) {
+                    new_jobs.extend(children.iter().map(|schema| {
+                        FetchJob::FetchVolumes(api::FetchVolumes {
+                            catalog_name: schema.catalog_name.clone(),
+                            schema_name: schema.name.clone(),
+                        })
+                    }));
+                }
+                if options.function_catalogs.contains(&c.catalog_name) {
+                    new_jobs.extend(children.iter().map(|schema| {
+                        FetchJob::FetchFunctions(api::FetchFunctions {
+                            catalog_name: schema.catalog_name.clone(),
+                            schema_name: schema.name.clone(),
+                        })
+                    }));
+                }
+                if options.tag_catalogs.contains(&c.catalog_name) {
+                    new_jobs.extend(
+                        children
+                            .iter()
+                            .map(|schema| tags_job(&schema.catalog_name, Some(&schema.name), None, None)),
+                    );
+                }
+                let listed: HashSet<String> = children.iter().map(|schema| schema.name.clone()).collect();
+                new_elements.extend(children.into_iter().map(|schema| {
+                    UnityCatalogElement::Schema(Schema {
+                        _name: schema.name,
+                        _catalog_name: schema.catalog_name,
+                        owner: schema.owner,
+                        grants: Grants::new(),
+                        comment: schema.comment,
+                        tags: Tags::new(),
+                        updated_at: schema.updated_at.and_then(DateTime::from_timestamp_millis),
+                        tables: BTreeMap::new(),
+                        volumes: BTreeMap::new(),
+                        functions: BTreeMap::new(),
+                    })
+                }));
+                if let Some(summaries) = summaries {
+                    let cached = cache.catalogs.get(&c.catalog_name);
+                    let tables: Vec<Table> = summaries
+                        .into_iter()
+                        .filter_map(table_from_summary)
+                        // A schema created since the schemas were listed has no element to go in.
+                        .filter(|table| listed.contains(&table.schema_name))
+                        .map(|table| {
+                            let cached = cached
+                                .and_then(|c| c.schemas.get(&table.schema_name))
+                                .and_then(|s| s.tables.get(&table.name))
+                                .filter(|c| {
+                                    !c.brief && c.table_id == table.table_id && c.updated_at == table.updated_at
+                                });
+                            cached.map_or(table, cached_table)
+                        })
+                        .collect();
+                    debug!("Found {} tables in {} from table summaries", tables.len(), c.catalog_name);
+                    new_jobs.extend(table_jobs(options, &tables));
+                    new_elements.extend(tables.into_iter().map(UnityCatalogElement::Table));
+                }
+            }
+            FetchJob::FetchSchema(s) => {
+                let catalog_name = s.catalog_name.clone();
+                let schema_name = s.schema_name.clone();
+                debug!("Fetching schema {}.{}", catalog_name, schema_name);
+                let cached = cache.catalogs.get(&catalog_name).and_then(|c| c.schemas.get(&schema_name));
+                let schema_unchanged = s.updated_at.is_some()
+                    && cached.is_some_and(|c| c.updated_at.map(|t| t.timestamp_millis()) == s.updated_at);
+                let brief = match !schema_unchanged && (cached.is_some() || options.brief) {
+                    true => backend.list_tables_brief(&catalog_name, &schema_name).await.ok(),
+                    false => None,
+                };
+                let children: Result<Vec<Table>> = match (cached, brief) {
+                    (Some(cached), _) if schema_unchanged => {
+                        let count = cached.tables.len();
+                        debug!("Reusing {} cached tables of unchanged {}.{}", count, catalog_name, schema_name);
+                        Ok(cached.tables.values().map(cached_table).collect())
+                    }
+                    (Some(cached), Some(brief)) if tables_unchanged(cached, &brief) => {
+                        debug!("Reusing {} cached tables of {}.{}", brief.len(), catalog_name, schema_name);
+                        Ok(cached.tables.values().map(cached_table).collect())
+                    }
+                    (_, Some(brief)) if options.brief => Ok(brief
+                        .into_iter()
+                        .map(|table| Table {
+                            brief: true,
+                            ..table_from_api(table)
+                        })
+                        .collect()),
+                    _ => backend.list_tables(&catalog_name, &schema_name).await.map(|c| c.into_iter().map(table_from_api).collect()),
+                };
+                let children = children?;
+                debug!(
+                    "Found {} tables in {}.{}",
+                    children.len(),
+                    catalog_name,
+                    schema_name
+                );
+                new_jobs.extend(table_jobs(options, &children));
+                new_elements.extend(children.into_iter().map(UnityCatalogElement::Table));
+            }
+            FetchJob::FetchTable(t) => {
+                debug!("Fetching table {}.{}.{}", t.catalog_name, t.schema_name, t.table_name);
+                let table = backend.get_table(&t.catalog_name, &t.schema_name, &t.table_name).await?;
+                if options.tag_catalogs.contains(&t.catalog_name) {
+                    new_jobs.extend(table.columns.iter().map(|column| {
+                        tags_job(&t.catalog_name, Some(&t.schema_name), Some(&t.table_name), Some(&column.name))
+                    }));
+                }
+                new_elements.push(UnityCatalogElement::TableDetails(table_from_api(table)));
+            }
+            FetchJob::FetchVolumes(v) => {
+                debug!("Fetching volumes of {}.{}", v.catalog_name, v.schema_name);
+                let volumes = backend.list_volumes(&v.catalog_name, &v.schema_name).await?;
+                new_elements.extend(volumes.into_iter().map(|volume| {
+                    UnityCatalogElement::Volume(Volume {
+                        name: volume.name,
+                        schema_name: volume.schema_name,
+                        catalog_name: volume.catalog_name,
+                        volume_type: volume.volume_type,
+                        storage_location: volume.storage_location,
+                        _updated_at: DateTime::from_timestamp_millis(volume.updated_at).unwrap(),
+                    })
+                }));
+            }
+            FetchJob::FetchFunctions(f) => {
+                debug!("Fetching functions of {}.{}", f.catalog_name, f.schema_name);
+                let functions = backend.list_functions(&f.catalog_name, &f.schema_name).await?;
+                new_elements.extend(functions.into_iter().map(|function| {
+                    let returns = match function.data_type.as_deref() {
+                        Some("TABLE_TYPE") => FunctionReturns::Table(function_parameters(function.return_params)),
+                        _ => FunctionReturns::Scalar(function.full_data_type.unwrap_or_default()),
+                    };
+                    UnityCatalogElement::Function(Function {
+                        name: function.name,
+                        schema_name: function.schema_name,
+                        catalog_name: function.catalog_name,
+                        parameters: function_parameters(function.input_params),
+                        returns,
+                        language: match function.routine_body.as_deref() {
+                            Some("EXTERNAL") => function.external_language,
+                            _ => None,
+                        },
+                        deterministic: function.is_deterministic.unwrap_or(true),
+                        comment: function.comment,
+                        definition: function.routine_definition.unwrap_or_default(),
+                    })
+                }));
+            }
+            FetchJob::FetchPermissions(p) => {
+                debug!("Fetching permissions for {:?}", (&p.catalog_name, &p.schema_name, &p.table_name));
+                let (securable_type, full_name) = p.securable();
+                let assignments = backend.list_permissions(securable_type, &full_name).await?;
+                let mut grants = Grants::new();
+                for assignment in assignments {
+                    grants
+                        .entry(assignment.principal)
+                        .or_default()
+                        .extend(assignment.privileges);
+                }
+                new_elements.push(UnityCatalogElement::Permissions(Permissions {
+                    catalog_name: p.catalog_name,
+                    schema_name: p.schema_name,
+                    table_name: p.table_name,
+                    grants,
+                }));
+            }
+            FetchJob::FetchTags(t) => {
+                debug!("Fetching tags for {:?}", (&t.catalog_name, &t.schema_name, &t.table_name, &t.column_name));
+                let (entity_type, entity_name) = t.entity();
+                let assignments = backend.list_tags(entity_type, &entity_name).await?;
+                new_elements.push(UnityCatalogElement::Tags(TagAssignments {
+                    catalog_name: t.catalog_name,
+                    schema_name: t.schema_name,
+                    table_name: t.table_name,
+                    column_name: t.column_name,
+                    tags: assignments
+                        .into_iter()
+                        .map(|a| (a.tag_key, a.tag_value.unwrap_or_default()))
+                        .collect(),
+                }));
+            }
+        }
+        Ok((new_jobs, new_elements))
+    }
+}
+
+type JobFuture<'a> = Pin<Box<dyn Future<Output = Result<(Vec<FetchJob>, Vec<UnityCatalogElement>)>> + Send + 'a>>;
+
+/// Controls which optional metadata is fetched while crawling.
+struct CrawlOptions {
+    /// Catalogs whose grants are synced, so their permissions need fetching.
+    permission_catalogs: HashSet<String>,
+    /// Catalogs whose tags are synced, so their tag assignments need fetching.
+    tag_catalogs: HashSet<String>,
+    /// Catalogs whose volumes are synced.
+    volume_catalogs: HashSet<String>,
+    /// Catalogs whose functions are synced.
+    function_catalogs: HashSet<String>,
+    /// List tables without columns and properties, see `CrawlMode::Fast`.
+    brief: bool,
+}
+
+fn crawl_options(entries: &[SyncEntry]) -> CrawlOptions {
+    CrawlOptions {
+        permission_catalogs: entries
+            .iter()
+            .filter(|c| c.permissions.is_some())
+            .flat_map(|c| std::iter::once(&c.catalog).chain(c.pinned_catalogs.iter()))
+            .cloned()
+            .collect(),
+        tag_catalogs: entries
+            .iter()
+            .filter(|c| c.metadata.as_ref().is_some_and(|m| m.tags))
+            .flat_map(|c| std::iter::once(&c.catalog).chain(c.pinned_catalogs.iter()))
+            // Masking by tag needs the source's column tags.
+            .chain(
+                entries
+                    .iter()
+                    .filter(|c| c.masking.iter().any(|rule| rule.tag.is_some()))
+                    .map(|c| &c.catalog),
+            )
+            .cloned()
+            .collect(),
+        volume_catalogs: entries
+            .iter()
+            .filter(|c| c.volumes.is_some())
+            .flat_map(|c| std::iter::once(&c.catalog).chain(c.pinned_catalogs.iter()))
+            .cloned()
+            .collect(),
+        function_catalogs: entries
+            .iter()
+            .filter(|c| c.functions)
+            .flat_map(|c| std::iter::once(&c.catalog).chain(c.pinned_catalogs.iter()))
+            .cloned()
+            .collect(),
+        brief: false,
+    }
+}
+
+/// Jobs fetching the grants and tags of freshly listed tables.
+fn table_jobs(options: &CrawlOptions, tables: &[Table]) -> Vec<FetchJob> {
+    let mut jobs = vec![];
+    for table in tables {
+        let (catalog_name, schema_name) = (&table.catalog_name, Some(table.schema_name.as_str()));
+        if options.permission_catalogs.contains(catalog_name) {
+            jobs.push(permissions_job(catalog_name, schema_name, Some(&table.name)));
+        }
+        if options.tag_catalogs.contains(catalog_name) {
+            jobs.push(tags_job(catalog_name, schema_name, Some(&table.name), None));
+            jobs.extend(
+                table
+                    .columns
+                    .iter()
+                    .map(|column| tags_job(catalog_name, schema_name, Some(&table.name), Some(&column.name))),
+            );
+        }
+    }
+    jobs
+}
+
+fn permissions_job(catalog_name: &str, schema_name: Option<&str>, table_name: Option<&str>) -> FetchJob {
+    FetchJob::FetchPermissions(api::FetchPermissions {
+        catalog_name: catalog_name.to_string(),
+        schema_name: schema_name.map(str::to_string),
+        table_name: table_name.map(str::to_string),
+    })
+}
+
+fn tags_job(catalog_name: &str, schema_name: Option<&str>, table_name: Option<&str>, column_name: Option<&str>) -> FetchJob {
+    FetchJob::FetchTags(api::FetchTags {
+        catalog_name: catalog_name.to_string(),
+        schema_name: schema_name.map(str::to_string),
+        table_name: table_name.map(str::to_string),
+        column_name: column_name.map(str::to_string),
+    })
+}
+
+/// Whether a request failed because the object does not exist.
+fn is_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| status == reqwest::StatusCode::NOT_FOUND)
+}
+
+/// A catalog as listed by the API, without its schemas.
+pub fn catalog_from_api(catalog: api::Catalog) -> Catalog {
+    let catalog_type = match catalog.catalog_type.as_deref() {
+        Some("FOREIGN_CATALOG") => CatalogType::Foreign {
+            connection_name: catalog.connection_name,
+        },
+        Some("DELTASHARING_CATALOG") => CatalogType::DeltaSharing {
+            provider_name: catalog.provider_name,
+            share_name: catalog.share_name,
+        },
+        Some("SYSTEM_CATALOG") => CatalogType::System,
+        _ => CatalogType::Managed,
+    };
+    Catalog {
+        name: catalog.name,
+        catalog_type,
+        owner: catalog.owner,
+        grants: Grants::new(),
+        comment: catalog.comment,
+        tags: Tags::new(),
+        schemas: BTreeMap::new(),
+    }
+}
+
+fn table_from_api(table: api::Table) -> Table {
+    Table {
+        name: table.name,
+        schema_name: table.schema_name,
+        catalog_name: table.catalog_name,
+        table_type: table.table_type,
+        data_source_format: table.data_source_format,
+        updated_at: DateTime::from_timestamp_millis(table.updated_at).unwrap(),
+        _updated_by: table.updated_by,
+        table_id: table.table_id,
+        properties: table.properties,
+        owner: table.owner,
+        grants: Grants::new(),
+        comment: table.comment,
+        tags: Tags::new(),
+        view_definition: table.view_definition,
+        columns: table
+            .columns
+            .into_iter()
+            .map(|column| Column {
+                name: column.name,
+                type_text: column.type_text,
+                nullable: column.nullable.unwrap_or(true),
+                position: column.position,
+                comment: column.comment,
+                tags: Tags::new(),
+            })
+            .collect(),
+        brief: false,
+    }
+}
+
+/// A brief table from its summary, or `None` when the full name is not `catalog.schema.table`.
+fn table_from_summary(summary: api::TableSummary) -> Option<Table> {
+    let mut parts = summary.full_name.splitn(3, '.');
+    let (catalog_name, schema_name, name) = (parts.next()?, parts.next()?, parts.next()?);
+    Some(Table {
+        name: name.to_string(),
+        schema_name: schema_name.to_string(),
+        catalog_name: catalog_name.to_string(),
+        table_type: summary.table_type,
+        data_source_format: summary.data_source_format,
+        updated_at: DateTime::from_timestamp_millis(summary.updated_at).unwrap(),
+        _updated_by: summary.updated_by.unwrap_or_default(),
+        table_id: summary.table_id,
+        properties: Default::default(),
+        owner: summary.owner,
+        grants: Grants::new(),
+        comment: None,
+        tags: Tags::new(),
+        view_definition: None,
+        columns: vec![],
+        brief: true,
+    })
+}
+
+/// Whether a schema still holds exactly the cached tables, none of them
+/// recreated or updated since. Any DDL, property change or write moves
+/// `updated_at`, so the cached columns and properties are still accurate.
+fn tables_unchanged(cached: &Schema, brief: &[api::Table]) -> bool {
+    brief.len() == cached.tables.len()
+        && brief.iter().all(|table| {
+            cached.tables.get(&table.name).is_some_and(|c| {
+                !c.brief && c.table_id == table.table_id && c.updated_at.timestamp_millis() == table.updated_at
+            })
+        })
+}
+
+/// A cached table without its grants and tags, which do not move `updated_at`
+/// and are fetched again.
+fn cached_table(table: &Table) -> Table {
+    Table {
+        grants: Grants::new(),
+        tags: Tags::new(),
+        columns: table
+            .columns
+            .iter()
+            .map(|column| Column {
+                tags: Tags::new(),
+                ..column.clone()
+            })
+            .collect(),
+        ..table.clone()
+    }
+}
+
+fn function_parameters(parameters: Option<api::FunctionParameterInfos>) -> Vec<FunctionParameter> {
+    let mut parameters = parameters.map(|p| p.parameters).unwrap_or_default();
+    parameters.sort_by_key(|p| p.position);
+    parameters
+        .into_iter()
+        .map(|p| FunctionParameter {
+            name: p.name,
+            type_text: p.type_text,
+            default: p.parameter_default,
+            comment: p.comment,
+        })
+        .collect()
+}
+
+/// Brief tables the diff of `source` against `target` reads details from.
+pub fn tables_needing_details(
+    source: &Catalog,
+    target: Option<&Catalog>,
+    options: &DiffOptions,
+) -> BTreeSet<(String, String, String)> {
+    source
+        .tables_needing_details(target, options)
+        .into_iter()
+        .map(|t| (t.catalog_name.clone(), t.schema_name.clone(), t.name.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::StatementResponse;
+    use anyhow::{bail, Context};
+    use async_trait::async_trait;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Answers requests from canned responses, keyed like `list_tables prod.sales`,
+    /// and records them in order.
+    #[derive(Default)]
+    struct FakeMetastore {
+        responses: HashMap<String, Value>,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl FakeMetastore {
+        fn new(responses: impl IntoIterator<Item = (&'static str, Value)>) -> Self {
+            Self {
+                responses: responses.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+                ..Default::default()
+            }
+        }
+
+        fn respond<T: DeserializeOwned>(&self, request: String) -> Result<T> {
+            self.requests.lock().unwrap().push(request.clone());
+            let response = self.responses.get(&request).with_context(|| format!("unexpected {}", request))?;
+            Ok(serde_json::from_value(response.clone())?)
+        }
+
+        fn requests(&self) -> Vec<String> {
+            std::mem::take(&mut self.requests.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl MetastoreBackend for FakeMetastore {
+        async fn list_catalogs(&self) -> Result<Vec<api::Catalog>> {
+            self.respond("list_catalogs".to_string())
+        }
+
+        async fn get_catalog(&self, catalog_name: &str) -> Result<api::Catalog> {
+            self.respond(format!("get_catalog {}", catalog_name))
+        }
+
+        async fn list_schemas(&self, catalog_name: &str) -> Result<Vec<api::Schema>> {
+            self.respond(format!("list_schemas {}", catalog_name))
+        }
+
+        async fn list_tables(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<api::Table>> {
+            self.respond(format!("list_tables {}.{}", catalog_name, schema_name))
+        }
+
+        async fn list_tables_brief(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<api::Table>> {
+            self.respond(format!("list_tables_brief {}.{}", catalog_name, schema_name))
+        }
+
+        async fn get_table(&self, catalog_name: &str, schema_name: &str, table_name: &str) -> Result<api::Table> {
+            self.respond(format!("get_table {}.{}.{}", catalog_name, schema_name, table_name))
+        }
+
+        async fn start_statement(&self, _warehouse_id: &str, _statement: &str) -> Result<StatementResponse> {
+            bail!("statements are not supported by the fake metastore")
+        }
+
+        async fn list_table_summaries(&self, catalog_name: &str) -> Result<Option<Vec<api::TableSummary>>> {
+            self.respond(format!("list_table_summaries {}", catalog_name)).map(Some)
+        }
+
+        async fn list_volumes(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<api::Volume>> {
+            self.respond(format!("list_volumes {}.{}", catalog_name, schema_name))
+        }
+    }
+
+    fn table(schema: &str, name: &str, updated_at: i64) -> Value {
+        json!({
+            "name": name,
+            "table_id": format!("{}-id", name),
+            "catalog_name": "prod",
+            "schema_name": schema,
+            "table_type": "MANAGED",
+            "updated_at": updated_at,
+            "updated_by": "etl",
+        })
+    }
+
+    fn crawler(backend: &Arc<FakeMetastore>, mode: CrawlMode, cache_path: PathBuf) -> Crawler {
+        Crawler {
+            backend: backend.clone(),
+            concurrency: 1,
+            mode,
+            cache_path,
+            no_cache: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_schemas_updated_since_the_cache_are_listed_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let metastore = |hr_updated_at: i64| {
+            Arc::new(FakeMetastore::new([
+                ("get_catalog prod", json!({ "name": "prod" })),
+                (
+                    "list_schemas prod",
+                    json!([
+                        { "name": "sales", "catalog_name": "prod", "updated_at": 1 },
+                        { "name": "hr", "catalog_name": "prod", "updated_at": hr_updated_at },
+                    ]),
+                ),
+                ("list_tables prod.sales", json!([table("sales", "orders", 1)])),
+                ("list_tables prod.hr", json!([table("hr", "people", 1)])),
+                ("list_tables_brief prod.hr", json!([table("hr", "people", 1)])),
+            ]))
+        };
+        let catalogs = HashSet::from(["prod".to_string()]);
+
+        let first = metastore(1);
+        crawler(&first, CrawlMode::Full, dir.path().join("cache.json")).crawl(&catalogs, &[]).await.unwrap();
+        assert_eq!(
+            first.requests(),
+            ["get_catalog prod", "list_schemas prod", "list_tables prod.sales", "list_tables prod.hr"]
+        );
+
+        let second = metastore(2);
+        let unity_catalog = crawler(&second, CrawlMode::Full, dir.path().join("cache.json"))
+            .crawl(&catalogs, &[])
+            .await
+            .unwrap();
+        assert_eq!(second.requests(), ["get_catalog prod", "list_schemas prod", "list_tables_brief prod.hr"]);
+        let schemas = &unity_catalog.catalogs["prod"].schemas;
+        assert!(schemas["sales"].tables.contains_key("orders"));
+        assert!(schemas["hr"].tables.contains_key("people"));
+    }
+
+    #[test]
+    fn cache_file_names_are_flat() {
+        assert_eq!(cache_file_name("https://adb-123.4.azuredatabricks.net"), "adb-123.4.azuredatabricks.net.json");
+        assert_eq!(cache_file_name("http://127.0.0.1:8080/"), "127.0.0.1_8080.json");
+        assert_eq!(cache_file_name("http://localhost:8080/api/2.1"), "localhost_8080_api_2.1.json");
+    }
+
+    #[tokio::test]
+    async fn fast_crawls_take_tables_from_summaries_and_keep_schemas_without_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let summaries = json!([
+            { "full_name": "prod.sales.orders", "table_type": "MANAGED", "table_id": "orders-id", "updated_at": 1 },
+            { "full_name": "prod.sales.v_orders", "table_type": "VIEW", "table_id": "v_orders-id", "updated_at": 1 },
+        ]);
+        let metastore = Arc::new(FakeMetastore::new([
+            ("get_catalog prod", json!({ "name": "prod" })),
+            ("list_table_summaries prod", summaries),
+            (
+                "list_schemas prod",
+                json!([{ "name": "sales", "catalog_name": "prod" }, { "name": "files", "catalog_name": "prod" }]),
+            ),
+            ("list_volumes prod.sales", json!([])),
+            (
+                "list_volumes prod.files",
+                json!([{
+                    "name": "raw",
+                    "catalog_name": "prod",
+                    "schema_name": "files",
+                    "volume_type": "MANAGED",
+                    "updated_at": 1,
+                }]),
+            ),
+        ]));
+        let entries: Vec<SyncEntry> =
+            serde_yaml::from_str("- catalog: prod\n  pinned_catalogs: []\n  volumes: {}").unwrap();
+        let catalogs = HashSet::from(["prod".to_string()]);
+
+        let unity_catalog = crawler(&metastore, CrawlMode::Fast, dir.path().join("cache.json"))
+            .crawl(&catalogs, &entries)
+            .await
+            .unwrap();
+        assert_eq!(
+            metastore.requests(),
+            [
+                "get_catalog prod",
+                "list_table_summaries prod",
+                "list_schemas prod",
+                "list_volumes prod.sales",
+                "list_volumes prod.files",
+            ]
+        );
+        let prod = &unity_catalog.catalogs["prod"];
+        assert!(prod.schemas["sales"].tables.values().all(|table| table.brief));
+        assert_eq!(prod.schemas["sales"].tables.len(), 2);
+        assert!(prod.schemas["files"].tables.is_empty());
+        assert!(prod.schemas["files"].volumes.contains_key("raw"));
+        // The view has no definition in its summary, the table is only detailed when its target is outdated.
+        let details = tables_needing_details(prod, None, &DiffOptions::default());
+        assert_eq!(details, BTreeSet::from([("prod".to_string(), "sales".to_string(), "v_orders".to_string())]));
+    }
+
+    #[tokio::test]
+    async fn failing_table_summaries_fail_the_crawl() {
+        let dir = tempfile::tempdir().unwrap();
+        let metastore = Arc::new(FakeMetastore::new([("get_catalog prod", json!({ "name": "prod" }))]));
+        let error = crawler(&metastore, CrawlMode::Fast, dir.path().join("cache.json"))
+            .crawl(&HashSet::from(["prod".to_string()]), &[])
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(format!("{:#}", error), "crawl failed: unexpected list_table_summaries prod");
+    }
+}
@@ -0,0 +1,199 @@
+use crate::crawler::{catalog_from_api, tables_needing_details, Crawler};
+use crate::metastore::MetastoreBackend;
+use crate::plan::diff_options;
+use crate::querygen::Query;
+use crate::schema::{self, SyncConfig, SyncEntry};
+use crate::unitycatalog::{Catalog, DiffNode, DiffOptions, DiffTree, Object, Operation, Tags};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use log::debug;
+use std::collections::HashSet;
+
+/// Tag recording who created an ephemeral catalog.
+pub const OWNER_TAG: &str = "dbsync_owner";
+/// Tag recording when an ephemeral catalog may be garbage collected, in RFC 3339.
+pub const EXPIRES_AT_TAG: &str = "dbsync_expires_at";
+
+/// Parses a time to live such as `3d`, `12h` or `30m`.
+pub fn parse_ttl(ttl: &str) -> Result<TimeDelta, String> {
+    let split = ttl.len() - ttl.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (amount, unit) = ttl.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| format!("invalid ttl {}", ttl))?;
+    match unit {
+        "m" => Ok(TimeDelta::minutes(amount)),
+        "h" => Ok(TimeDelta::hours(amount)),
+        "d" => Ok(TimeDelta::days(amount)),
+        "w" => Ok(TimeDelta::weeks(amount)),
+        _ => Err(format!("invalid ttl {}, expected a number followed by m, h, d or w", ttl)),
+    }
+}
+
+/// Creates a catalog holding clones of `source`, tagged with its owner and expiry.
+pub fn create<'a>(source: &'a Catalog, owner: &str, expires_at: DateTime<Utc>, options: &DiffOptions) -> DiffNode<'a> {
+    let mut node = source.diff(None, options).unwrap();
+    node.children.insert(
+        0,
+        DiffNode {
+            operation: Some(Operation::SetTags {
+                object: Object::Catalog(source),
+                tags: vec![
+                    (OWNER_TAG.to_string(), owner.to_string()),
+                    (EXPIRES_AT_TAG.to_string(), expires_at.to_rfc3339()),
+                ],
+            }),
+            children: vec![],
+        },
+    );
+    node
+}
+
+/// Catalogs without an expiry tag were not created by `ephemeral create` and never expire.
+pub fn is_expired(tags: &Tags, now: DateTime<Utc>) -> bool {
+    tags.get(EXPIRES_AT_TAG)
+        .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+        .is_some_and(|expires_at| expires_at <= now)
+}
+
+/// Drops the expired catalogs.
+pub fn gc(catalogs: &[Catalog], now: DateTime<Utc>) -> Vec<DiffNode<'_>> {
+    catalogs
+        .iter()
+        .filter(|catalog| is_expired(&catalog.tags, now))
+        .map(|catalog| DiffNode {
+            operation: Some(Operation::DropCatalog {
+                catalog,
+                reason: format!("expired at {}", catalog.tags[EXPIRES_AT_TAG]),
+            }),
+            children: vec![],
+        })
+        .collect()
+}
+
+/// Queries that create `name` from the `from` catalog, which is crawled with the
+/// options of its config entry if it has one. Fails if `name` does not match the
+/// ephemeral name pattern or already exists. Without an `owner`, the current
+/// user owns the new catalog.
+pub async fn plan_create(
+    crawler: &Crawler,
+    config: &SyncConfig,
+    from: &str,
+    name: &str,
+    owner: Option<String>,
+    expires_at: DateTime<Utc>,
+) -> Result<Vec<Query>> {
+    let backend = crawler.backend.as_ref();
+    if !schema::glob_to_regex(&config.ephemeral.name_pattern).is_match(name) {
+        bail!(
+            "{} does not match the ephemeral name pattern {} and would never be garbage collected",
+            name, config.ephemeral.name_pattern
+        );
+    }
+    let existing = backend.list_catalogs().await?;
+    if existing.iter().any(|c| c.name == name) {
+        bail!("catalog {} already exists", name);
+    }
+    if !existing.iter().any(|c| c.name == from) {
+        bail!("source catalog {} does not exist", from);
+    }
+    let owner = match owner {
+        Some(owner) => owner,
+        None => backend.current_user().await?,
+    };
+
+    // Sync the same things as the configured entry for the source, if there is one.
+    let entry = config.catalogs.iter().find(|c| c.catalog == from);
+    let entries: &[SyncEntry] = entry.map(std::slice::from_ref).unwrap_or_default();
+    let mut unity_catalog = crawler.crawl(&HashSet::from([from.to_string()]), entries).await?;
+
+    let source = unity_catalog
+        .catalogs
+        .get(from)
+        .with_context(|| format!("source catalog {} was not crawled", from))?;
+    let clone_method = source.catalog_type.clone_method();
+    let options = match entry {
+        Some(entry) => diff_options(entry, name, clone_method),
+        None => DiffOptions {
+            clone_method,
+            ..Default::default()
+        },
+    };
+    let details = tables_needing_details(source, None, &options);
+    crawler.fetch_details(&mut unity_catalog, details, entries).await?;
+    let source = &unity_catalog.catalogs[from];
+    let diff = create(source, &owner, expires_at, &options);
+    Ok(Query::from_diff_node(&diff, name).flatten())
+}
+
+/// Queries that drop the expired catalogs matching the ephemeral name pattern.
+/// Catalogs synced by the config are never dropped, whatever their name.
+pub async fn plan_gc(backend: &dyn MetastoreBackend, config: &SyncConfig, now: DateTime<Utc>) -> Result<Vec<Query>> {
+    let pattern = schema::glob_to_regex(&config.ephemeral.name_pattern);
+    let configured: HashSet<&String> = config
+        .catalogs
+        .iter()
+        .flat_map(|c| std::iter::once(&c.catalog).chain(c.pinned_catalogs.iter()))
+        .collect();
+    let mut candidates = vec![];
+    for catalog in backend.list_catalogs().await? {
+        if !pattern.is_match(&catalog.name) || configured.contains(&catalog.name) {
+            continue;
+        }
+        let tags = backend.list_tags("catalogs", &catalog.name).await?;
+        let mut catalog = catalog_from_api(catalog);
+        catalog.tags = tags
+            .into_iter()
+            .map(|a| (a.tag_key, a.tag_value.unwrap_or_default()))
+            .collect();
+        candidates.push(catalog);
+    }
+    let queries: Vec<Query> = gc(&candidates, now)
+        .iter()
+        .flat_map(|node| Query::from_diff_node(node, "").flatten())
+        .collect();
+    debug!("{} of {} ephemeral catalogs expired", queries.len(), candidates.len());
+    Ok(queries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::querygen::format_plan;
+    use crate::querygen::tests::{catalog, schema, table};
+
+    #[test]
+    fn created_catalogs_are_tagged_with_their_owner_and_expiry() {
+        let orders = table("prod", "sales", "orders", "MANAGED", 0);
+        let source = catalog("prod", vec![schema("prod", "sales", vec![orders])]);
+        let expires_at = DateTime::parse_from_rfc3339("2026-10-21T09:00:00Z").unwrap().to_utc();
+        let diff = create(&source, "alice@example.com", expires_at, &DiffOptions::default());
+        insta::assert_snapshot!(format_plan(&Query::from_diff_node(&diff, "dev_alice").flatten()), @r"
+        CREATE CATALOG dev_alice;
+        ALTER CATALOG dev_alice SET TAGS ('dbsync_owner' = 'alice@example.com', 'dbsync_expires_at' = '2026-10-21T09:00:00+00:00');
+        CREATE SCHEMA dev_alice.sales;
+        CREATE TABLE dev_alice.sales.orders SHALLOW CLONE prod.sales.orders;
+        ALTER TABLE dev_alice.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+        ");
+    }
+
+    #[test]
+    fn only_tagged_catalogs_past_their_expiry_are_dropped() {
+        let now = DateTime::parse_from_rfc3339("2026-10-21T09:00:00Z").unwrap().to_utc();
+        let tagged = |name: &str, expires_at: DateTime<Utc>| {
+            let mut c = catalog(name, vec![]);
+            c.tags.insert(EXPIRES_AT_TAG.to_string(), expires_at.to_rfc3339());
+            c
+        };
+        let catalogs = [
+            tagged("dev_expired", now - TimeDelta::hours(1)),
+            tagged("dev_fresh", now + TimeDelta::hours(1)),
+            catalog("dev_untagged", vec![]),
+        ];
+        let queries: Vec<Query> = gc(&catalogs, now)
+            .iter()
+            .flat_map(|node| Query::from_diff_node(node, "").flatten())
+            .collect();
+        insta::assert_snapshot!(format_plan(&queries), @r"
+        DROP CATALOG dev_expired CASCADE;
+        ");
+    }
+}
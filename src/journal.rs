@@ -0,0 +1,171 @@
+use crate::apply::{self, Limits, Outcome, Status};
+use crate::metastore::MetastoreBackend;
+use crate::plan::Plan;
+use crate::report::Run;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// A plan saved when applying it starts, with the outcome of every operation so
+/// far. It is rewritten after each operation, so an interrupted apply can be
+/// resumed where it stopped, and removed once every operation succeeded.
+#[derive(Serialize, Deserialize)]
+pub struct Journal {
+    pub plan_id: String,
+    pub created_at: DateTime<Utc>,
+    pub runs: Vec<Run>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Saves the runs of `plan` under a new plan id in `dir`.
+    pub fn new(plan: Plan, dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let created_at = Utc::now();
+        let plan_id = created_at.format("%Y%m%d-%H%M%S-%3f").to_string();
+        let journal = Self {
+            path: dir.join(format!("{}.json", plan_id)),
+            plan_id,
+            created_at,
+            runs: plan.runs,
+        };
+        journal.save()?;
+        Ok(journal)
+    }
+
+    /// Reads the journal of `plan_id` from `dir`, or the most recent one without an id.
+    pub fn load(dir: &Path, plan_id: Option<&str>) -> Result<Self> {
+        let path = match plan_id {
+            Some(plan_id) => dir.join(format!("{}.json", plan_id)),
+            // Plan ids start with their creation time, so the last one is the latest.
+            None => std::fs::read_dir(dir)
+                .ok()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension().is_some_and(|e| e == "json"))
+                .max()
+                .with_context(|| format!("no interrupted apply to resume in {}", dir.display()))?,
+        };
+        let json = std::fs::read_to_string(&path).with_context(|| format!("reading journal {}", path.display()))?;
+        let mut journal: Self =
+            serde_json::from_str(&json).with_context(|| format!("parsing journal {}", path.display()))?;
+        journal.path = path;
+        Ok(journal)
+    }
+
+    fn save(&self) -> Result<()> {
+        // Write next to the journal and rename, so an interruption never leaves a partial file.
+        let temporary = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Checks that `fresh`, planned from the current state of the catalogs, needs
+    /// exactly the statements that are still to run in this plan, except those of
+    /// failed or cancelled operations, which may have partly run. Anything else means
+    /// the catalogs or the config changed since, and the rest of the plan cannot be trusted.
+    pub fn verify(&self, fresh: &Plan) -> Result<()> {
+        let pairs = |runs: &[Run]| -> Vec<(String, String)> {
+            runs.iter().map(|run| (run.source.clone(), run.target.clone())).collect()
+        };
+        if pairs(&self.runs) != pairs(&fresh.runs) {
+            bail!("plan {} is out of date: the config now syncs other catalogs", self.plan_id);
+        }
+        for (saved, fresh) in self.runs.iter().zip(&fresh.runs) {
+            let remaining: HashSet<&str> = pending(saved)
+                .into_iter()
+                .flat_map(|index| &saved.queries[index].statements)
+                .map(String::as_str)
+                .collect();
+            let needed: HashSet<&str> = fresh
+                .queries
+                .iter()
+                .flat_map(|query| &query.statements)
+                .map(String::as_str)
+                .collect();
+            if let Some(statement) = needed.difference(&remaining).min() {
+                bail!(
+                    "plan {} is out of date: {} now needs `{}`, which is not among its remaining operations",
+                    self.plan_id,
+                    saved.target,
+                    statement
+                );
+            }
+            // A failed or cancelled operation may have run some of its statements already.
+            let untouched: HashSet<&str> = pending(saved)
+                .into_iter()
+                .filter(|&index| {
+                    saved
+                        .outcomes
+                        .get(index)
+                        .is_none_or(|outcome| !matches!(outcome.status, Status::Failed | Status::Cancelled))
+                })
+                .flat_map(|index| &saved.queries[index].statements)
+                .map(String::as_str)
+                .collect();
+            if let Some(statement) = untouched.difference(&needed).min() {
+                bail!(
+                    "plan {} is out of date: {} no longer needs `{}`, which is among its remaining operations",
+                    self.plan_id,
+                    saved.target,
+                    statement
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every operation that has not succeeded yet, recording each outcome as
+    /// soon as it is known. Like [`Plan::apply`], a failure only stops the run of
+    /// its pinned catalog, while `limits` stopping applying leaves every later
+    /// operation to resume.
+    pub async fn apply(&mut self, backend: &dyn MetastoreBackend, warehouse_id: &str, limits: &Limits) -> Result<()> {
+        for index in 0..self.runs.len() {
+            let run = &mut self.runs[index];
+            if run.outcomes.is_empty() {
+                run.outcomes = vec![Outcome::new(Status::Planned); run.queries.len()];
+            }
+            let pending = pending(run);
+            let completed = run.queries.len() - pending.len();
+            if completed > 0 {
+                info!("{} -> {}: skipping {} operations completed earlier", run.source, run.target, completed);
+            }
+            let queries: Vec<_> = pending.iter().map(|&query| run.queries[query].clone()).collect();
+            apply::apply(backend, warehouse_id, &queries, limits, |position, outcome| {
+                self.runs[index].outcomes[pending[position]] = outcome.clone();
+                if let Err(e) = self.save() {
+                    warn!("Could not write journal {}: {:#}", self.path.display(), e);
+                }
+            })
+            .await;
+            let run = &self.runs[index];
+            debug!("{} -> {}: {}", run.source, run.target, run.summary());
+        }
+        if self.completed() {
+            std::fs::remove_file(&self.path).with_context(|| format!("removing journal {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Whether every operation has succeeded, so nothing is left to resume.
+    pub fn completed(&self) -> bool {
+        self.runs.iter().all(|run| pending(run).is_empty())
+    }
+}
+
+/// Indices of the queries of a run that have not succeeded yet.
+fn pending(run: &Run) -> Vec<usize> {
+    (0..run.queries.len())
+        .filter(|&index| {
+            run.outcomes
+                .get(index)
+                .is_none_or(|outcome| !matches!(outcome.status, Status::Succeeded | Status::Skipped))
+        })
+        .collect()
+}
@@ -0,0 +1,63 @@
+//! Synchronizes Databricks Unity Catalogs across environments.
+//!
+//! A [`Crawler`] fetches catalogs into a [`UnityCatalog`] tree from a [`MetastoreBackend`],
+//! either a Databricks workspace through a [`Client`] or an open-source Unity Catalog server.
+//! [`DiffTree::diff`] compares a source catalog with a pinned catalog, and a
+//! [`Plan`] holds the resulting statements in execution order, ready to print or
+//! to apply on a SQL warehouse.
+//!
+//! ```no_run
+//! use dbsync::{load_config, metastore, CrawlMode, Crawler, Plan};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let config = load_config("config.yaml")?;
+//! let crawler = Crawler {
+//!     backend: metastore::from_config(&config),
+//!     concurrency: 10,
+//!     mode: CrawlMode::Full,
+//!     cache_path: ".dbsync-cache/crawl.json".into(),
+//!     no_cache: false,
+//! };
+//! let plan = Plan::new(&crawler, &config.catalogs, &[]).await?;
+//! for run in &plan.runs {
+//!     println!("{} -> {}: {}", run.source, run.target, run.summary());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+/// Client and response types of the Databricks REST API.
+pub mod api;
+/// Runs flattened queries on a SQL warehouse.
+pub mod apply;
+/// Fetches catalogs from the API into a [`UnityCatalog`] tree.
+pub mod crawler;
+/// Short-lived catalogs that are dropped once they expire.
+pub mod ephemeral;
+/// Saved plans of applies in progress, so they can be resumed.
+pub mod journal;
+/// Interface to the metastores that catalogs are crawled from.
+pub mod metastore;
+/// Open-source Unity Catalog server backend.
+pub mod oss;
+/// Plans that sync the pinned catalogs of config entries.
+pub mod plan;
+/// Turns diffs into SQL statements.
+pub mod querygen;
+/// Plans and run results for people and for automation.
+pub mod report;
+/// The YAML config.
+pub mod schema;
+/// Syncs config entries on cron schedules.
+pub mod serve;
+/// REST API to plan and apply config entries.
+pub mod server;
+/// The catalog tree and how two trees are diffed.
+pub mod unitycatalog;
+
+pub use api::Client;
+pub use crawler::{CrawlMode, Crawler};
+pub use metastore::MetastoreBackend;
+pub use plan::Plan;
+pub use schema::{load_config, SyncConfig, SyncEntry};
+pub use unitycatalog::{DiffNode, DiffTree, UnityCatalog};
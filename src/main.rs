@@ -1,102 +1,351 @@
-mod api;
-mod schema;
-use api::{FetchJob};
-use clap::Parser;
-use futures::stream::StreamExt;
-use log::debug;
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::future::Future;
-use std::path::PathBuf;
-use std::pin::Pin;
-use tokio::{self};
-use unitycatalog::{Catalog, DiffTree, Schema, Table, UnityCatalog, UnityCatalogElement};
-use chrono::DateTime;
-mod querygen;
-
-mod unitycatalog;
+use anyhow::{bail, Context, Result};
+use chrono::{TimeDelta, Utc};
+use clap::{Parser, Subcommand};
+use dbsync::crawler::cache_file_name;
+use dbsync::journal::Journal;
+use dbsync::plan::{configured_catalogs, diffs, load_catalogs};
+use dbsync::querygen::{self, Query};
+use dbsync::report::{self, Report};
+use dbsync::{apply, ephemeral, metastore, schema, serve, server, CrawlMode, Crawler, MetastoreBackend, Plan, SyncConfig};
+use log::{debug, error, info, warn};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(short, long)]
     config_path: PathBuf,
+    /// Maximum number of concurrent API requests while crawling.
+    #[arg(long, default_value_t = 10)]
+    num_request: usize,
+    /// How much of each table the first pass of a crawl fetches.
+    #[arg(long, value_enum, default_value_t = CrawlMode::Full)]
+    crawl_mode: CrawlMode,
+    /// Directory of the crawl cache, one file per host.
+    #[arg(long, default_value = ".dbsync-cache")]
+    cache_dir: PathBuf,
+    /// Crawl every schema instead of reusing unchanged ones from the cache.
     #[arg(long)]
-    num_request: Option<usize>,
-}
-
-async fn process_job(
-    job: FetchJob,
-    client: &api::Client,
-) -> (Vec<FetchJob>, Vec<UnityCatalogElement>) {
-    let mut new_jobs = Vec::new();
-    let mut new_elements = Vec::new();
-
-    match job {
-        FetchJob::FetchAllCatalogs(j) => {
-            debug!("Fetching all catalogs");
-            if let Ok(children) = j.get_children(client).await {
-                new_jobs.extend(children.iter().map(|catalog| FetchJob::FetchCatalog(api::FetchCatalog {
-                    catalog_name: catalog.name.clone(),
-                })));
-                new_elements.extend(children.iter().map(|catalog| {
-                    UnityCatalogElement::Catalog(Catalog {
-                        name: catalog.name.clone(),
-                        schemas: HashMap::new(),
-                    })
-                }));
-            }
+    no_cache: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the statements that sync every pinned catalog. This is the default.
+    Plan(PlanArgs),
+    /// Run the statements that sync every pinned catalog on the configured warehouse.
+    Apply(ApplyArgs),
+    /// Check that every pinned catalog is in sync without changing anything. Exits
+    /// with 0 when in sync, 2 when any pinned catalog drifted and 1 on errors.
+    Check(CheckArgs),
+    /// Sync every catalog that has a `schedule` on that schedule, until SIGTERM.
+    Serve(ServeArgs),
+    /// Serve a local REST API to plan and apply config entries, until SIGTERM.
+    Server(ServerArgs),
+    /// Crawl catalogs and save them as a JSON snapshot that `plan` can use instead.
+    Snapshot {
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Catalog to include, can be repeated. Defaults to every catalog in the config.
+        #[arg(long)]
+        catalog: Vec<String>,
+    },
+    /// Manage short-lived catalogs, such as one per pull request.
+    Ephemeral {
+        #[command(subcommand)]
+        command: EphemeralCommand,
+    },
+}
+
+#[derive(clap::Args, Debug, Default)]
+struct PlanArgs {
+    /// Snapshot to read catalogs from instead of crawling them, can be repeated.
+    #[arg(long)]
+    snapshot: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    format: PlanFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+enum PlanFormat {
+    /// The SQL statements of the plan.
+    #[default]
+    Sql,
+    /// A coloured tree with the reason for every operation and counts per kind.
+    Terminal,
+    /// Markdown tables, for pasting into pull requests.
+    Markdown,
+    /// A self-contained HTML page.
+    Html,
+    /// One JSON document with every operation, see `report::SCHEMA_VERSION`.
+    Json,
+    /// One JSON object per operation and line.
+    Jsonl,
+}
+
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+    /// Snapshot to read catalogs from instead of crawling them, can be repeated.
+    #[arg(long)]
+    snapshot: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    format: ApplyFormat,
+    /// Resume an interrupted apply, the latest one without a plan id. Operations
+    /// that succeeded are skipped and failed ones are retried, once a fresh plan
+    /// confirms the rest of the saved plan still holds.
+    #[arg(long, value_name = "PLAN_ID", conflicts_with = "snapshot")]
+    resume: Option<Option<String>>,
+    /// Cancel and fail a statement still running after this long, such as `15m`.
+    #[arg(long, value_parser = apply::parse_duration)]
+    statement_timeout: Option<Duration>,
+    /// Stop applying after this long, such as `2h`, cancelling running statements.
+    /// The rest of the plan can be resumed.
+    #[arg(long, value_parser = apply::parse_duration)]
+    deadline: Option<Duration>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+    /// Snapshot to read catalogs from instead of crawling them, can be repeated.
+    #[arg(long)]
+    snapshot: Vec<PathBuf>,
+    /// Also write a JUnit XML report with one test case per pinned catalog.
+    #[arg(long)]
+    junit: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Only plan on schedule, without running any statements.
+    #[arg(long)]
+    dry_run: bool,
+    /// JSON file with the next and last run of every scheduled catalog.
+    /// Defaults to status.json in the cache directory.
+    #[arg(long)]
+    status_file: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServerArgs {
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: std::net::SocketAddr,
+    /// Snapshot to read catalogs from instead of crawling them, can be repeated.
+    #[arg(long)]
+    snapshot: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+enum ApplyFormat {
+    /// Log progress only.
+    #[default]
+    Text,
+    /// One JSON document with the status, duration and error of every operation.
+    Json,
+    /// One JSON object per operation and line.
+    Jsonl,
+}
+
+#[derive(clap::Args, Debug)]
+struct CreateArgs {
+    /// Source catalog to clone.
+    #[arg(long)]
+    from: String,
+    /// Name of the new catalog. Must match the configured ephemeral name pattern.
+    #[arg(long)]
+    name: String,
+    /// Time until the catalog may be garbage collected, such as 12h or 3d.
+    #[arg(long, value_parser = ephemeral::parse_ttl, default_value = "1d")]
+    ttl: TimeDelta,
+    /// Defaults to the user the access token belongs to.
+    #[arg(long)]
+    owner: Option<String>,
+    /// Print the statements instead of running them.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum EphemeralCommand {
+    /// Create a catalog filled with clones of a source catalog.
+    Create(CreateArgs),
+    /// Drop expired catalogs that match the ephemeral name pattern.
+    Gc {
+        /// Print the statements instead of running them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+fn print_queries(queries: &[Query]) {
+    print!("{}", querygen::format_plan(queries));
+}
+
+/// Prints the queries on a dry run, runs them on the configured warehouse otherwise.
+async fn run_queries(backend: &dyn MetastoreBackend, config: &SyncConfig, queries: &[Query], dry_run: bool) -> Result<()> {
+    if dry_run {
+        print_queries(queries);
+        return Ok(());
+    }
+    let warehouse_id = config
+        .warehouse_id
+        .as_deref()
+        .context("warehouse_id must be configured to run statements")?;
+    let outcomes = apply::apply(backend, warehouse_id, queries, &apply::Limits::default(), |_, _| {}).await;
+    match outcomes.into_iter().find_map(|o| o.error) {
+        Some(error) => bail!("{}", error),
+        None => Ok(()),
+    }
+}
+
+async fn snapshot(
+    crawler: &Crawler,
+    config: &SyncConfig,
+    output: &Path,
+    catalogs: Vec<String>,
+) -> Result<()> {
+    let catalogs = match catalogs.is_empty() {
+        true => configured_catalogs(&config.catalogs),
+        false => catalogs.into_iter().collect(),
+    };
+    let unity_catalog = crawler.crawl(&catalogs, &config.catalogs).await?;
+    unity_catalog
+        .save(output)
+        .with_context(|| format!("writing snapshot {}", output.display()))?;
+    info!("Saved {} catalogs to {}", unity_catalog.catalogs.len(), output.display());
+    Ok(())
+}
+
+async fn plan(crawler: &Crawler, config: &SyncConfig, args: PlanArgs) -> Result<()> {
+    let unity_catalog = load_catalogs(crawler, &config.catalogs, &args.snapshot).await?;
+    let reports: Vec<Report> = match args.format {
+        PlanFormat::Terminal | PlanFormat::Markdown | PlanFormat::Html => diffs(&config.catalogs, &unity_catalog)?
+            .into_iter()
+            .map(|(source, target, diff)| Report::new(source, target, diff.as_ref()))
+            .collect(),
+        _ => vec![],
+    };
+    let plan = Plan::from_catalogs(&config.catalogs, &unity_catalog)?;
+    match args.format {
+        PlanFormat::Sql => plan.runs.iter().for_each(|run| print_queries(&run.queries)),
+        PlanFormat::Terminal => {
+            let colour = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            print!("{}", report::render_terminal(&reports, colour));
         }
-        FetchJob::FetchCatalog(c) => {
-            debug!("Fetching catalog {}", c.catalog_name);
-            if let Ok(children) = c.get_children(client).await {
-                new_jobs.extend(children.iter().map(|schema| {
-                    FetchJob::FetchSchema(api::FetchSchema {
-                        catalog_name: schema.catalog_name.clone(),
-                        schema_name: schema.name.clone(),
-                    })
-                }));
-                new_elements.extend(children.iter().map(|schema| {
-                    UnityCatalogElement::Schema(Schema {
-                        _name: schema.name.clone(),
-                        _catalog_name: schema.catalog_name.clone(),
-                        tables: HashMap::new(),
-                    })
-                }));
-            }
+        PlanFormat::Markdown => print!("{}", report::render_markdown(&reports)),
+        PlanFormat::Html => print!("{}", report::render_html(&reports)),
+        PlanFormat::Json => print!("{}", report::render_json(&plan.runs)),
+        PlanFormat::Jsonl => print!("{}", report::render_jsonl(&plan.runs)),
+    }
+    Ok(())
+}
+
+async fn apply(crawler: &Crawler, config: &SyncConfig, args: ApplyArgs, cache_dir: &Path) -> Result<()> {
+    let warehouse_id = config
+        .warehouse_id
+        .as_deref()
+        .context("warehouse_id must be configured to run statements")?;
+    let started = tokio::time::Instant::now();
+    let journal_dir = cache_dir.join("journal");
+    let mut journal = match &args.resume {
+        Some(plan_id) => {
+            let journal = Journal::load(&journal_dir, plan_id.as_deref())?;
+            journal.verify(&Plan::new(crawler, &config.catalogs, &[]).await?)?;
+            info!("Resuming plan {}", journal.plan_id);
+            journal
+        }
+        None => {
+            let journal = Journal::new(Plan::new(crawler, &config.catalogs, &args.snapshot).await?, &journal_dir)?;
+            info!("Applying plan {}", journal.plan_id);
+            journal
         }
-        FetchJob::FetchSchema(s) => {
-            let catalog_name = s.catalog_name.clone();
-            let schema_name = s.schema_name.clone();
-            debug!("Fetching schema {}.{}", catalog_name, schema_name);
-            if let Ok(children) = s.get_children(client).await {
-                debug!(
-                    "Found {} tables in {}.{}",
-                    children.len(),
-                    catalog_name,
-                    schema_name
-                );
-                new_elements.extend(children.into_iter().map(|table| {
-                    UnityCatalogElement::Table(Table {
-                        name: table.name,
-                        schema_name: table.schema_name,
-                        catalog_name: table.catalog_name,
-                        table_type: table.table_type,
-                        data_source_format: table.data_source_format,
-                        updated_at: DateTime::from_timestamp_millis(table.updated_at).unwrap(),
-                        _updated_by: table.updated_by,
-                        _properties: table.properties,
-                    })
-                }));
+    };
+    let (cancel, cancelled) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            warn!("Stopping, cancelling running statements. Press Ctrl-C again to exit right away");
+            cancel.send_replace(true);
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
             }
         }
+    });
+    let limits = apply::Limits {
+        statement_timeout: args.statement_timeout,
+        deadline: args.deadline.map(|deadline| started + deadline),
+        cancel: Some(cancelled),
+    };
+    journal.apply(crawler.backend.as_ref(), warehouse_id, &limits).await?;
+    // On stderr whatever the log level, so it is seen after Ctrl-C and stays out of JSON output.
+    for run in &journal.runs {
+        eprintln!("{} -> {}: {}", run.source, run.target, run.summary());
+    }
+    match args.format {
+        ApplyFormat::Text => {}
+        ApplyFormat::Json => print!("{}", report::render_json(&journal.runs)),
+        ApplyFormat::Jsonl => print!("{}", report::render_jsonl(&journal.runs)),
+    }
+    if !journal.completed() && limits.stopped() {
+        bail!(
+            "applying was stopped before it completed, resume with `dbsync apply --resume {}`",
+            journal.plan_id
+        );
     }
-  (new_jobs, new_elements)
+    let failed: Vec<&str> = journal.runs.iter().filter(|run| run.failed()).map(|run| run.target.as_str()).collect();
+    if !failed.is_empty() {
+        bail!(
+            "applying failed for {}, retry with `dbsync apply --resume {}`",
+            failed.join(", "),
+            journal.plan_id
+        );
+    }
+    Ok(())
 }
 
-fn create_job_future(
-    job: FetchJob,
-    client: &api::Client,
-) -> Pin<Box<dyn Future<Output = (Vec<FetchJob>, Vec<UnityCatalogElement>)> + Send + '_>> {
-    Box::pin(process_job(job, client))
+async fn serve(crawler: &Crawler, config: &SyncConfig, args: ServeArgs, cache_dir: &Path) -> Result<()> {
+    let warehouse_id = match args.dry_run {
+        true => None,
+        false => Some(
+            config
+                .warehouse_id
+                .as_deref()
+                .context("warehouse_id must be configured to run statements")?,
+        ),
+    };
+    let status_file = args.status_file.unwrap_or_else(|| cache_dir.join("status.json"));
+    serve::serve(crawler, config, warehouse_id, &status_file).await
+}
+
+/// Prints the drift of every pinned catalog and returns whether all are in sync.
+async fn check(crawler: &Crawler, config: &SyncConfig, args: CheckArgs) -> Result<i32> {
+    let unity_catalog = load_catalogs(crawler, &config.catalogs, &args.snapshot).await?;
+    let reports: Vec<Report> = diffs(&config.catalogs, &unity_catalog)?
+        .into_iter()
+        .map(|(source, target, diff)| Report::new(source, target, diff.as_ref()))
+        .collect();
+    print!("{}", report::render_check(&reports));
+    if let Some(path) = &args.junit {
+        std::fs::write(path, report::render_junit(&reports))
+            .with_context(|| format!("writing JUnit report {}", path.display()))?;
+    }
+    Ok(report::check_exit_code(&reports))
+}
+
+async fn ephemeral_create(crawler: &Crawler, config: &SyncConfig, args: CreateArgs) -> Result<()> {
+    let expires_at = Utc::now() + args.ttl;
+    let queries = ephemeral::plan_create(crawler, config, &args.from, &args.name, args.owner, expires_at).await?;
+    run_queries(crawler.backend.as_ref(), config, &queries, args.dry_run).await?;
+    if !args.dry_run {
+        info!("Created {} from {}, expires at {}", args.name, args.from, expires_at.to_rfc3339());
+    }
+    Ok(())
+}
+
+async fn ephemeral_gc(backend: &dyn MetastoreBackend, config: &SyncConfig, dry_run: bool) -> Result<()> {
+    let queries = ephemeral::plan_gc(backend, config, Utc::now()).await?;
+    run_queries(backend, config, &queries, dry_run).await
 }
 
 #[tokio::main]
@@ -104,75 +353,44 @@ async fn main() {
     env_logger::init();
     let args = Args::parse();
     debug!("Parsing config");
-    let config = schema::load_config(&args.config_path.to_str().unwrap()).unwrap();
-    let mut catalogs: HashSet<String> = HashSet::new();
-    let mut unity_catalog = UnityCatalog::new();
-    config.catalogs.iter().for_each(|c| {
-        catalogs.insert(c.catalog.clone());
-        c.pinned_catalogs.iter().for_each(|c| {
-            catalogs.insert(c.clone());
-        });
-    });
-    debug!("Catalogs: {:?}", catalogs);
-
-    let client = api::Client::new(&config.host, &config.pat);
-
-    // Create a queue for pending jobs
-    let mut job_queue: VecDeque<FetchJob> = catalogs
-        .iter()
-        .map(|c| {
-            unity_catalog.insert_assume_ordered(UnityCatalogElement::Catalog(Catalog {
-                name: c.clone(),
-                schemas: HashMap::new(),
-            }));
-            FetchJob::FetchCatalog(api::FetchCatalog {
-                catalog_name: c.clone(),
-            })
-        })
-        .collect();
-
-    // let mut job_queue: VecDeque<FetchJob> = VecDeque::new();
-    // job_queue.push_back(FetchJob::FetchAllCatalogs(FetchAllCatalogs {}));
-
-    let mut active_futures = futures::stream::FuturesUnordered::new();
-
-    while active_futures.len() < 10 && !job_queue.is_empty() {
-        if let Some(job) = job_queue.pop_front() {
-            active_futures.push(create_job_future(job, &client));
-        }
-    }
-
-    while !active_futures.is_empty() {
-        if let Some((new_jobs, uc_elements)) = active_futures.next().await {
-            uc_elements.into_iter().for_each(|element| {
-                unity_catalog.insert_assume_ordered(element);
-            });
-            job_queue.extend(new_jobs);
-            while active_futures.len() < 10 && !job_queue.is_empty() {
-                if let Some(job) = job_queue.pop_front() {
-                    active_futures.push(create_job_future(job, &client));
-                }
-            }
+    let config = match schema::load_config(&args.config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
         }
-    }
+    };
+    let crawler = Crawler {
+        backend: metastore::from_config(&config),
+        concurrency: args.num_request,
+        mode: args.crawl_mode,
+        cache_path: args.cache_dir.join(cache_file_name(&config.host)),
+        no_cache: args.no_cache,
+    };
 
-    for catalog in config.catalogs {
-        for pinned_catalog in catalog.pinned_catalogs {
-            let mut queries = VecDeque::new();
-            let cat = unity_catalog.catalogs.get(&catalog.catalog).unwrap();
-            let pinned_cat = unity_catalog.catalogs.get(&pinned_catalog).unwrap();
-            let diff = cat.diff(Some(pinned_cat)).unwrap();
-            let query = querygen::Query::from_diff_node(&diff, &pinned_catalog);
-            queries.push_back(query);
-
-            while !queries.is_empty() {
-                let query = queries.pop_front().unwrap();
-                if let Some(query_string) = query.query {
-                    println!("{}", query_string);
-                }
-                queries.extend(query.children);
-            }
-            
+    let result = match args.command.unwrap_or(Command::Plan(PlanArgs::default())) {
+        Command::Plan(plan_args) => plan(&crawler, &config, plan_args).await,
+        Command::Apply(apply_args) => apply(&crawler, &config, apply_args, &args.cache_dir).await,
+        Command::Serve(serve_args) => serve(&crawler, &config, serve_args, &args.cache_dir).await,
+        Command::Server(server_args) => {
+            let server = server::Server::new(config, crawler, server_args.snapshot);
+            server::run_server(server, server_args.listen).await
         }
+        Command::Check(check_args) => match check(&crawler, &config, check_args).await {
+            Ok(0) => Ok(()),
+            Ok(code) => std::process::exit(code),
+            Err(e) => Err(e),
+        },
+        Command::Snapshot { output, catalog } => snapshot(&crawler, &config, &output, catalog).await,
+        Command::Ephemeral {
+            command: EphemeralCommand::Create(create),
+        } => ephemeral_create(&crawler, &config, create).await,
+        Command::Ephemeral {
+            command: EphemeralCommand::Gc { dry_run },
+        } => ephemeral_gc(crawler.backend.as_ref(), &config, dry_run).await,
+    };
+    if let Err(e) = result {
+        error!("{:#}", e);
+        std::process::exit(1);
     }
 }
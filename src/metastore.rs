@@ -0,0 +1,90 @@
+use crate::api::{
+    self, Catalog, Function, PrivilegeAssignment, Schema, StatementResponse, Table, TableSummary, TagAssignment, Volume,
+};
+use crate::oss::OssClient;
+use crate::schema::{MetastoreKind, SyncConfig};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A Unity Catalog metastore that catalogs are crawled from and statements run on.
+///
+/// Listing catalogs, schemas and tables and starting statements are required.
+/// Everything else defaults to an error. The crawler fails on it like on any
+/// other failed request, so a plan that needs an unsupported call, such as
+/// `list_tags` on an open-source server for an entry that syncs tags, fails.
+/// Only table summaries are optional: without them `--crawl-mode fast` lists
+/// every schema brief.
+#[async_trait]
+pub trait MetastoreBackend: Send + Sync {
+    async fn list_catalogs(&self) -> Result<Vec<Catalog>>;
+
+    async fn get_catalog(&self, catalog_name: &str) -> Result<Catalog>;
+
+    async fn list_schemas(&self, catalog_name: &str) -> Result<Vec<Schema>>;
+
+    async fn list_tables(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Table>>;
+
+    async fn get_table(&self, catalog_name: &str, schema_name: &str, table_name: &str) -> Result<Table>;
+
+    /// Starts a statement on a SQL warehouse. Quick statements may already be done
+    /// when this returns, others are followed with [`Self::statement_state`].
+    async fn start_statement(&self, warehouse_id: &str, statement: &str) -> Result<StatementResponse>;
+
+    /// Lists the tables without their columns and properties, which is enough to
+    /// tell whether any of them changed.
+    async fn list_tables_brief(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Table>> {
+        self.list_tables(catalog_name, schema_name).await
+    }
+
+    /// Every table in the catalog without its columns and properties, in far fewer
+    /// requests than listing each schema. `None` when the metastore has no table summaries.
+    async fn list_table_summaries(&self, _catalog_name: &str) -> Result<Option<Vec<TableSummary>>> {
+        Ok(None)
+    }
+
+    async fn list_volumes(&self, _catalog_name: &str, _schema_name: &str) -> Result<Vec<Volume>> {
+        bail!("volumes are not supported by this metastore")
+    }
+
+    async fn list_functions(&self, _catalog_name: &str, _schema_name: &str) -> Result<Vec<Function>> {
+        bail!("functions are not supported by this metastore")
+    }
+
+    /// Grants on a securable, see [`api::FetchPermissions::securable`].
+    async fn list_permissions(&self, _securable_type: &str, _full_name: &str) -> Result<Vec<PrivilegeAssignment>> {
+        bail!("permissions are not supported by this metastore")
+    }
+
+    /// Tags of an entity, see [`api::FetchTags::entity`].
+    async fn list_tags(&self, _entity_type: &str, _entity_name: &str) -> Result<Vec<TagAssignment>> {
+        bail!("tags are not supported by this metastore")
+    }
+
+    async fn statement_state(&self, _statement_id: &str) -> Result<StatementResponse> {
+        bail!("statements are not supported by this metastore")
+    }
+
+    /// Asks the warehouse to stop a statement that is still running.
+    async fn cancel_statement(&self, _statement_id: &str) -> Result<()> {
+        bail!("statements are not supported by this metastore")
+    }
+
+    /// User name of the access token.
+    async fn current_user(&self) -> Result<String> {
+        bail!("the current user is not known to this metastore")
+    }
+
+    /// Recursively copies every file under `source` to the same relative path under `target`.
+    async fn copy_directory(&self, _source: &str, _target: &str) -> Result<usize> {
+        bail!("copying files is not supported by this metastore")
+    }
+}
+
+/// The backend the config points at.
+pub fn from_config(config: &SyncConfig) -> Arc<dyn MetastoreBackend> {
+    match config.metastore {
+        MetastoreKind::Databricks => Arc::new(api::Client::new(&config.host, &config.pat)),
+        MetastoreKind::Oss => Arc::new(OssClient::new(&config.host, &config.pat)),
+    }
+}
@@ -0,0 +1,140 @@
+use crate::api::{Catalog, Function, PrivilegeAssignment, Schema, StatementResponse, Table, Volume};
+use crate::metastore::MetastoreBackend;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest_middleware::{reqwest, ClientBuilder, ClientWithMiddleware, RequestBuilder};
+use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Client for the open-source Unity Catalog server, whose API lives under
+/// `/api/2.1/unity-catalog` and is usually served over plain HTTP.
+///
+/// The server has no SQL engine, so plans can be made against it but not applied.
+#[derive(Clone)]
+pub struct OssClient {
+    base_url: String,
+    client: ClientWithMiddleware,
+    token: Option<String>,
+}
+
+impl OssClient {
+    /// `host` is `host:port`, which is reached over HTTP, or a URL with a scheme.
+    /// An empty `token` sends no authorization header.
+    pub fn new(host: &str, token: &str) -> Self {
+        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .build();
+        let root = match host.contains("://") {
+            true => host.trim_end_matches('/').to_string(),
+            false => format!("http://{}", host),
+        };
+        Self {
+            base_url: format!("{}/api/2.1/unity-catalog", root),
+            client,
+            token: Some(token.to_string()).filter(|t| !t.is_empty()),
+        }
+    }
+
+    fn request(&self, endpoint: &str) -> RequestBuilder {
+        let request = self.client.get(format!("{}/{}", self.base_url, endpoint));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let mut value: Value = self.request(endpoint).send().await?.error_for_status()?.json().await?;
+        with_update_times(&mut value);
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Every page of a list endpoint, taking the items under `key` of each response.
+    async fn list<T: DeserializeOwned>(&self, endpoint: &str, key: &str, query: &[(&str, &str)]) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.request(endpoint).query(query);
+            if let Some(token) = &page_token {
+                request = request.query(&[("page_token", token)]);
+            }
+            let mut response: Value = request.send().await?.error_for_status()?.json().await?;
+            if let Some(Value::Array(page)) = response.get_mut(key) {
+                for item in page.iter_mut() {
+                    with_update_times(item);
+                }
+                items.extend(serde_json::from_value::<Vec<T>>(Value::Array(std::mem::take(page)))?);
+            }
+            page_token = response
+                .get("next_page_token")
+                .and_then(Value::as_str)
+                .filter(|token| !token.is_empty())
+                .map(str::to_string);
+            if page_token.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+}
+
+/// Objects that were never updated have no `updated_at` or `updated_by` on the
+/// open-source server. Their creation takes that place, as on Databricks.
+fn with_update_times(value: &mut Value) {
+    let Some(object) = value.as_object_mut() else {
+        return;
+    };
+    for (updated, created, default) in [
+        ("updated_at", "created_at", Value::from(0)),
+        ("updated_by", "created_by", Value::from("")),
+    ] {
+        if object.get(updated).is_none_or(Value::is_null) {
+            let fallback = object.get(created).filter(|v| !v.is_null()).cloned().unwrap_or(default);
+            object.insert(updated.to_string(), fallback);
+        }
+    }
+}
+
+#[async_trait]
+impl MetastoreBackend for OssClient {
+    async fn list_catalogs(&self) -> Result<Vec<Catalog>> {
+        self.list("catalogs", "catalogs", &[]).await
+    }
+
+    async fn get_catalog(&self, catalog_name: &str) -> Result<Catalog> {
+        self.get(&format!("catalogs/{}", catalog_name)).await
+    }
+
+    async fn list_schemas(&self, catalog_name: &str) -> Result<Vec<Schema>> {
+        self.list("schemas", "schemas", &[("catalog_name", catalog_name)]).await
+    }
+
+    async fn list_tables(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Table>> {
+        self.list("tables", "tables", &[("catalog_name", catalog_name), ("schema_name", schema_name)])
+            .await
+    }
+
+    async fn get_table(&self, catalog_name: &str, schema_name: &str, table_name: &str) -> Result<Table> {
+        self.get(&format!("tables/{}.{}.{}", catalog_name, schema_name, table_name)).await
+    }
+
+    async fn start_statement(&self, _warehouse_id: &str, _statement: &str) -> Result<StatementResponse> {
+        bail!("the open-source Unity Catalog server cannot run statements")
+    }
+
+    async fn list_volumes(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Volume>> {
+        self.list("volumes", "volumes", &[("catalog_name", catalog_name), ("schema_name", schema_name)])
+            .await
+    }
+
+    async fn list_functions(&self, catalog_name: &str, schema_name: &str) -> Result<Vec<Function>> {
+        self.list("functions", "functions", &[("catalog_name", catalog_name), ("schema_name", schema_name)])
+            .await
+    }
+
+    async fn list_permissions(&self, securable_type: &str, full_name: &str) -> Result<Vec<PrivilegeAssignment>> {
+        self.list(&format!("permissions/{}/{}", securable_type, full_name), "privilege_assignments", &[])
+            .await
+    }
+}
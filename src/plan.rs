@@ -0,0 +1,409 @@
+use crate::apply::{self, Limits};
+use crate::crawler::{tables_needing_details, Crawler};
+use crate::metastore::MetastoreBackend;
+use crate::querygen::Query;
+use crate::report::Run;
+use crate::schema::{self, SyncEntry};
+use crate::unitycatalog::{
+    Catalog, CloneMethod, ColumnMatcher, DiffNode, DiffOptions, DiffTree, MaskRule, MetadataOptions, PermissionOptions, Subset,
+    UnityCatalog, VolumeOptions,
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+
+/// Flattened queries that sync the pinned catalogs of some config entries, one run
+/// per pinned catalog in config order.
+pub struct Plan {
+    pub runs: Vec<Run>,
+}
+
+impl Plan {
+    /// Plans every pinned catalog of `entries`, reading catalogs from `snapshots`
+    /// and crawling the others.
+    pub async fn new(crawler: &Crawler, entries: &[SyncEntry], snapshots: &[PathBuf]) -> Result<Self> {
+        let unity_catalog = load_catalogs(crawler, entries, snapshots).await?;
+        Self::from_catalogs(entries, &unity_catalog)
+    }
+
+    /// Plans every pinned catalog of `entries` from catalogs that are already loaded.
+    /// Fails if a source catalog is missing from `unity_catalog`, pinned catalogs
+    /// missing from it are created.
+    pub fn from_catalogs(entries: &[SyncEntry], unity_catalog: &UnityCatalog) -> Result<Self> {
+        let runs = diffs(entries, unity_catalog)?
+            .into_iter()
+            .map(|(source, target, diff)| Run {
+                source: source.to_string(),
+                target: target.to_string(),
+                queries: flatten_diff(diff.as_ref(), target),
+                outcomes: vec![],
+            })
+            .collect();
+        Ok(Self { runs })
+    }
+
+    /// Runs the queries on a SQL warehouse and records their outcomes. A failure
+    /// stops the run of its pinned catalog, the other pinned catalogs still run.
+    pub async fn apply(&mut self, backend: &dyn MetastoreBackend, warehouse_id: &str) {
+        for run in &mut self.runs {
+            run.outcomes = apply::apply(backend, warehouse_id, &run.queries, &Limits::default(), |_, _| {}).await;
+            info!("{} -> {}: {}", run.source, run.target, run.summary());
+        }
+    }
+
+    /// Whether any operation failed while applying.
+    pub fn failed(&self) -> bool {
+        self.runs.iter().any(Run::failed)
+    }
+}
+
+/// Options for diffing the source of `entry` against one of its pinned catalogs.
+pub fn diff_options(entry: &SyncEntry, pinned_catalog: &str, clone_method: CloneMethod) -> DiffOptions {
+    DiffOptions {
+        permissions: entry.permissions.as_ref().map(|p| PermissionOptions {
+            sync_grants: p.sync_grants,
+            sync_ownership: p.sync_ownership,
+            principal_mapping: p.principal_mapping.get(pinned_catalog).cloned().unwrap_or_default(),
+        }),
+        metadata: entry.metadata.as_ref().map(|m| MetadataOptions {
+            comments: m.comments,
+            tags: m.tags,
+        }),
+        volumes: entry.volumes.as_ref().map(|v| VolumeOptions {
+            managed: v.managed,
+            location_mapping: v.location_mapping.get(pinned_catalog).cloned().unwrap_or_default(),
+        }),
+        functions: entry.functions,
+        materialized_views: entry.materialized_views,
+        streaming_tables: entry.streaming_tables,
+        clone_method,
+        subsets: entry
+            .subsets
+            .iter()
+            .map(|s| {
+                (
+                    schema::glob_to_regex(&s.tables),
+                    Subset {
+                        sample_percent: s.sample_percent,
+                        predicate: s.predicate.clone(),
+                    },
+                )
+            })
+            .collect(),
+        masking: entry
+            .masking
+            .iter()
+            .map(|rule| MaskRule {
+                matcher: match (&rule.tag, &rule.columns) {
+                    (Some(tag), _) => match tag.split_once('=') {
+                        Some((key, value)) => ColumnMatcher::Tag {
+                            key: key.to_string(),
+                            value: Some(value.to_string()),
+                        },
+                        None => ColumnMatcher::Tag {
+                            key: tag.clone(),
+                            value: None,
+                        },
+                    },
+                    (None, columns) => {
+                        ColumnMatcher::Name(schema::glob_to_regex(&columns.as_deref().unwrap_or_default().to_lowercase()))
+                    }
+                },
+                transform: rule.transform.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Source and pinned catalogs of every config entry.
+pub fn configured_catalogs(entries: &[SyncEntry]) -> HashSet<String> {
+    let mut catalogs: HashSet<String> = HashSet::new();
+    entries.iter().for_each(|c| {
+        catalogs.insert(c.catalog.clone());
+        c.pinned_catalogs.iter().for_each(|c| {
+            catalogs.insert(c.clone());
+        });
+    });
+    catalogs
+}
+
+/// Catalogs of every entry, read from snapshots or crawled, with the table
+/// details that diffing them needs.
+pub async fn load_catalogs(crawler: &Crawler, entries: &[SyncEntry], snapshots: &[PathBuf]) -> Result<UnityCatalog> {
+    let mut snapshot = UnityCatalog::new();
+    for path in snapshots {
+        let loaded = UnityCatalog::load(path).with_context(|| format!("reading snapshot {}", path.display()))?;
+        snapshot.catalogs.extend(loaded.catalogs);
+    }
+    // Only catalogs missing from the snapshots are crawled.
+    let mut catalogs = configured_catalogs(entries);
+    catalogs.retain(|c| !snapshot.catalogs.contains_key(c));
+    debug!("Catalogs: {:?}", catalogs);
+
+    let mut unity_catalog = crawler.crawl(&catalogs, entries).await?;
+    unity_catalog.catalogs.extend(snapshot.catalogs);
+
+    // Foreign, Delta Sharing and system catalogs can only be read from.
+    for catalog in entries {
+        for pinned_catalog in &catalog.pinned_catalogs {
+            let Some(pinned) = unity_catalog.catalogs.get(pinned_catalog) else {
+                continue;
+            };
+            let catalog_type = &pinned.catalog_type;
+            if catalog_type.is_read_only() {
+                bail!(
+                    "{} cannot be pinned to {}, which is a read-only {} catalog",
+                    catalog.catalog, pinned_catalog, catalog_type
+                );
+            }
+        }
+    }
+
+    let mut details = BTreeSet::new();
+    for catalog in entries {
+        for pinned_catalog in &catalog.pinned_catalogs {
+            let cat = source(&unity_catalog, catalog)?;
+            let options = diff_options(catalog, pinned_catalog, cat.catalog_type.clone_method());
+            details.extend(tables_needing_details(cat, unity_catalog.catalogs.get(pinned_catalog), &options));
+        }
+    }
+    crawler.fetch_details(&mut unity_catalog, details, entries).await?;
+    Ok(unity_catalog)
+}
+
+/// Source, pinned catalog and diff of every pinned catalog, in config order.
+/// Fails if a source catalog is missing from `unity_catalog`, pinned catalogs
+/// missing from it are diffed against nothing so that they are created.
+pub fn diffs<'a>(
+    entries: &'a [SyncEntry],
+    unity_catalog: &'a UnityCatalog,
+) -> Result<Vec<(&'a str, &'a str, Option<DiffNode<'a>>)>> {
+    let mut diffs = vec![];
+    for catalog in entries {
+        for pinned_catalog in &catalog.pinned_catalogs {
+            let cat = source(unity_catalog, catalog)?;
+            let options = diff_options(catalog, pinned_catalog, cat.catalog_type.clone_method());
+            let pinned_cat = unity_catalog.catalogs.get(pinned_catalog);
+            diffs.push((catalog.catalog.as_str(), pinned_catalog.as_str(), cat.diff(pinned_cat, &options)));
+        }
+    }
+    Ok(diffs)
+}
+
+fn source<'a>(unity_catalog: &'a UnityCatalog, entry: &SyncEntry) -> Result<&'a Catalog> {
+    unity_catalog
+        .catalogs
+        .get(&entry.catalog)
+        .with_context(|| format!("source catalog {} not found", entry.catalog))
+}
+
+/// Queries of a diff in execution order, none for a diff without changes.
+pub fn flatten_diff(diff: Option<&DiffNode>, target_catalog: &str) -> Vec<Query> {
+    diff.map(|diff| Query::from_diff_node(diff, target_catalog).flatten())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::querygen::format_plan;
+    use crate::querygen::tests::{catalog, schema, table};
+    use crate::unitycatalog::CatalogType;
+    use std::sync::Arc;
+
+    fn entries(yaml: &str) -> Vec<SyncEntry> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn read_only_sources_are_copied_instead_of_cloned() {
+        let entries = entries(
+            "
+- catalog: lakehouse_pg
+  pinned_catalogs: [pg_dev]
+- catalog: partner_share
+  pinned_catalogs: [partner_dev]
+",
+        );
+        let mut foreign = catalog(
+            "lakehouse_pg",
+            vec![schema("lakehouse_pg", "public", vec![table("lakehouse_pg", "public", "orders", "FOREIGN", 0)])],
+        );
+        foreign.catalog_type = CatalogType::Foreign {
+            connection_name: Some("postgres".to_string()),
+        };
+        let mut share = catalog(
+            "partner_share",
+            vec![schema("partner_share", "events", vec![table("partner_share", "events", "clicks", "MANAGED", 0)])],
+        );
+        share.catalog_type = CatalogType::DeltaSharing {
+            provider_name: Some("partner".to_string()),
+            share_name: Some("clicks".to_string()),
+        };
+        let mut unity_catalog = UnityCatalog::new();
+        for c in [foreign, share, catalog("pg_dev", vec![]), catalog("partner_dev", vec![])] {
+            unity_catalog.catalogs.insert(c.name.clone(), c);
+        }
+
+        let plan = Plan::from_catalogs(&entries, &unity_catalog).unwrap();
+        let plans: Vec<String> = plan.runs.iter().map(|run| format_plan(&run.queries)).collect();
+        insta::assert_snapshot!(plans.join("\n"), @r"
+        CREATE SCHEMA pg_dev.public;
+        CREATE TABLE pg_dev.public.orders AS SELECT * FROM lakehouse_pg.public.orders;
+        ALTER TABLE pg_dev.public.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+
+        CREATE SCHEMA partner_dev.events;
+        CREATE TABLE partner_dev.events.clicks DEEP CLONE partner_share.events.clicks;
+        ALTER TABLE partner_dev.events.clicks SET TBLPROPERTIES ('dbsync.source_table_id' = 'clicks-id');
+        ");
+    }
+
+    #[tokio::test]
+    async fn read_only_catalogs_cannot_be_pinned() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut unity_catalog = UnityCatalog::new();
+        let mut system = catalog("system", vec![]);
+        system.catalog_type = CatalogType::System;
+        for c in [catalog("prod", vec![]), system] {
+            unity_catalog.catalogs.insert(c.name.clone(), c);
+        }
+        let snapshot = dir.path().join("snapshot.json");
+        unity_catalog.save(&snapshot).unwrap();
+        // Everything comes from the snapshot, so the backend is never called.
+        let crawler = Crawler {
+            backend: Arc::new(crate::oss::OssClient::new("127.0.0.1:9", "")),
+            concurrency: 1,
+            mode: crate::CrawlMode::Full,
+            cache_path: dir.path().join("cache.json"),
+            no_cache: true,
+        };
+
+        let entries = entries("- catalog: prod\n  pinned_catalogs: [system]");
+        let error = load_catalogs(&crawler, &entries, &[snapshot]).await.err().unwrap();
+        assert_eq!(error.to_string(), "prod cannot be pinned to system, which is a read-only SYSTEM catalog");
+    }
+
+    #[tokio::test]
+    async fn snapshots_plan_like_the_catalogs_they_were_saved_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut orders = table("prod", "sales", "orders", "MANAGED", 5);
+        orders.comment = Some("Orders".to_string());
+        orders.tags.insert("pii".to_string(), "true".to_string());
+        let mut prod = catalog(
+            "prod",
+            vec![schema("prod", "sales", vec![orders, table("prod", "sales", "v_orders", "VIEW", 0)])],
+        );
+        prod.grants.insert("analysts".to_string(), BTreeSet::from(["USE CATALOG".to_string()]));
+        let mut foreign = catalog(
+            "lakehouse_pg",
+            vec![schema("lakehouse_pg", "public", vec![table("lakehouse_pg", "public", "users", "FOREIGN", 0)])],
+        );
+        foreign.catalog_type = CatalogType::Foreign {
+            connection_name: Some("postgres".to_string()),
+        };
+        let dev = catalog("dev", vec![schema("dev", "sales", vec![table("dev", "sales", "orders", "MANAGED", 0)])]);
+        let mut unity_catalog = UnityCatalog::new();
+        for c in [prod, foreign, dev, catalog("pg_dev", vec![])] {
+            unity_catalog.catalogs.insert(c.name.clone(), c);
+        }
+        let config = entries(
+            "
+- catalog: prod
+  pinned_catalogs: [dev]
+  metadata: {}
+  permissions: {}
+- catalog: lakehouse_pg
+  pinned_catalogs: [pg_dev]
+",
+        );
+        let snapshot = dir.path().join("snapshot.json");
+        unity_catalog.save(&snapshot).unwrap();
+        // Everything comes from the snapshot, so the backend is never called.
+        let crawler = Crawler {
+            backend: Arc::new(crate::oss::OssClient::new("127.0.0.1:9", "")),
+            concurrency: 1,
+            mode: crate::CrawlMode::Full,
+            cache_path: dir.path().join("cache.json"),
+            no_cache: true,
+        };
+
+        let format = |plan: Plan| plan.runs.iter().map(|run| format_plan(&run.queries)).collect::<Vec<_>>().join("\n");
+        let crawled = format(Plan::from_catalogs(&config, &unity_catalog).unwrap());
+        let loaded = format(Plan::new(&crawler, &config, &[snapshot]).await.unwrap());
+        assert_eq!(loaded, crawled);
+        insta::assert_snapshot!(loaded, @r"
+        GRANT USE CATALOG ON CATALOG dev TO `analysts`;
+        DROP TABLE IF EXISTS dev.sales.orders;
+        CREATE TABLE dev.sales.orders SHALLOW CLONE prod.sales.orders;
+        ALTER TABLE dev.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+        COMMENT ON TABLE dev.sales.orders IS 'Orders';
+        ALTER TABLE dev.sales.orders SET TAGS ('pii' = 'true');
+        CREATE OR REPLACE VIEW dev.sales.v_orders AS SELECT * FROM dev.sales.base;
+
+        CREATE SCHEMA pg_dev.public;
+        CREATE TABLE pg_dev.public.users AS SELECT * FROM lakehouse_pg.public.users;
+        ALTER TABLE pg_dev.public.users SET TBLPROPERTIES ('dbsync.source_table_id' = 'users-id');
+        ");
+    }
+
+    #[test]
+    fn subsets_apply_to_the_tables_their_glob_matches() {
+        let config = entries(
+            "
+- catalog: prod
+  pinned_catalogs: [dev]
+  subsets:
+    - tables: marketing.*
+      sample_percent: 50
+    - tables: sales.fact_*
+      sample_percent: 1
+      where: event_date >= current_date() - 7
+    - tables: sales.*
+      where: region = 'EU'
+",
+        );
+        let tables = ["dim_customers", "fact_clicks"].map(|name| table("prod", "sales", name, "MANAGED", 0));
+        let mut unity_catalog = UnityCatalog::new();
+        for c in [
+            catalog(
+                "prod",
+                vec![
+                    schema("prod", "sales", tables.to_vec()),
+                    schema("prod", "ops", vec![table("prod", "ops", "runs", "MANAGED", 0)]),
+                ],
+            ),
+            catalog("dev", vec![schema("dev", "sales", vec![]), schema("dev", "ops", vec![])]),
+        ] {
+            unity_catalog.catalogs.insert(c.name.clone(), c);
+        }
+
+        let plan = Plan::from_catalogs(&config, &unity_catalog).unwrap();
+        insta::assert_snapshot!(format_plan(&plan.runs[0].queries), @r"
+        CREATE TABLE dev.ops.runs SHALLOW CLONE prod.ops.runs;
+        ALTER TABLE dev.ops.runs SET TBLPROPERTIES ('dbsync.source_table_id' = 'runs-id');
+        CREATE OR REPLACE TABLE dev.sales.dim_customers AS SELECT * FROM prod.sales.dim_customers WHERE region = 'EU';
+        ALTER TABLE dev.sales.dim_customers SET TBLPROPERTIES ('dbsync.source_table_id' = 'dim_customers-id');
+        ALTER TABLE dev.sales.dim_customers SET TBLPROPERTIES ('dbsync.source_updated_at' = '1700000000000', 'dbsync.subset' = 'WHERE region = \'EU\'');
+        CREATE OR REPLACE TABLE dev.sales.fact_clicks AS SELECT * FROM prod.sales.fact_clicks TABLESAMPLE (1 PERCENT) WHERE event_date >= current_date() - 7;
+        ALTER TABLE dev.sales.fact_clicks SET TBLPROPERTIES ('dbsync.source_table_id' = 'fact_clicks-id');
+        ALTER TABLE dev.sales.fact_clicks SET TBLPROPERTIES ('dbsync.source_updated_at' = '1700000000000', 'dbsync.subset' = 'TABLESAMPLE (1 PERCENT) WHERE event_date >= current_date() - 7');
+        ");
+
+        // A glob that matches no table leaves every table a full clone.
+        let config = entries(
+            "
+- catalog: prod
+  pinned_catalogs: [dev]
+  subsets:
+    - tables: marketing.*
+      sample_percent: 50
+",
+        );
+        let plan = Plan::from_catalogs(&config, &unity_catalog).unwrap();
+        let statements: Vec<&String> = plan.runs[0].queries.iter().flat_map(|q| &q.statements).collect();
+        assert_eq!(statements.iter().filter(|s| s.contains("SHALLOW CLONE")).count(), 3, "{:#?}", statements);
+        assert!(statements.iter().all(|s| !s.contains("AS SELECT")), "{:#?}", statements);
+    }
+}
+
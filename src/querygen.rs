@@ -1,79 +1,992 @@
-use crate::unitycatalog::{DiffNode, Operation};
+use crate::unitycatalog::{
+    rewrite_catalog_references, CloneMethod, CloneReason, DiffNode, FunctionParameter, FunctionReturns, Object, Operation, Table,
+    TableKind, CopySpec, MASKING_PROPERTY, SOURCE_TABLE_ID_PROPERTY, SOURCE_UPDATED_AT_PROPERTY, SUBSET_PROPERTY,
+};
+use crate::report::{describe, Entry};
+use crate::schema::MaskTransform;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
+/// Statements for one diff operation, with the queries for the objects below it.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Query {
-    pub query: Option<String>,
+    /// Statements in execution order.
+    pub statements: Vec<String>,
+    /// Whether the statements only write metadata, such as a shallow clone,
+    /// rather than copying data.
     pub is_fast: bool,
+    /// Volume directory to copy to a target directory through the Files API,
+    /// for steps that SQL cannot express.
+    pub copy_files: Option<(String, String)>,
+    /// Runs after all non-deferred statements of the plan. Views and functions
+    /// are deferred so the tables they reference exist in the target first, and
+    /// are ordered among each other by what they reference.
+    pub deferred: bool,
+    /// Explanation for objects that are intentionally not synced.
+    pub note: Option<String>,
+    /// What the operation does and why, for reports and structured output.
+    pub entry: Option<Entry>,
     pub children: Vec<Query>,
 }
 
 impl Query {
     pub fn from_diff_node(diff_node: &DiffNode, target_catalog: &str) -> Self {
-        match &diff_node.operation {
-            None => {
+        let mut query = Self::from_operation(diff_node, target_catalog);
+        query.entry = diff_node
+            .operation
+            .as_ref()
+            .map(|operation| describe(operation, target_catalog, 0));
+        query
+    }
+
+    /// A fast query running `statements`, followed by `children`.
+    fn new(statements: Vec<String>, children: Vec<Query>) -> Self {
+        Self {
+            statements,
+            is_fast: true,
+            copy_files: None,
+            deferred: false,
+            note: None,
+            entry: None,
+            children,
+        }
+    }
+
+    fn from_operation(diff_node: &DiffNode, target_catalog: &str) -> Self {
+        let children = || diff_node.children.iter().map(|child| Self::from_diff_node(child, target_catalog)).collect();
+        let Some(operation) = &diff_node.operation else {
+            return Self {
+                is_fast: false,
+                ..Self::new(vec![], children())
+            };
+        };
+        match operation {
+            Operation::CreateCatalog(_catalog) => Self::new(vec![format!("CREATE CATALOG {}", target_catalog)], children()),
+            Operation::CreateSchema(schema) => {
+                Self::new(vec![format!("CREATE SCHEMA {}.{}", target_catalog, schema._name)], children())
+            }
+            // No children for drops, as they get cascaded
+            Operation::DropCatalog { catalog, .. } => Self::new(vec![format!("DROP CATALOG {} CASCADE", catalog.name)], vec![]),
+            Operation::DropSchema(schema) => {
+                Self::new(vec![format!("DROP SCHEMA {}.{} CASCADE", target_catalog, schema._name)], vec![])
+            }
+            Operation::DropTable(table) => Self::new(
+                vec![format!("DROP {} {}.{}.{}", drop_keyword(table), target_catalog, table.schema_name, table.name)],
+                vec![],
+            ),
+            Operation::CloneTable { source, target, method, reason } => {
+                let mut queries = vec![];
+
+                // If there's an existing table to replace, drop it first
+                if let Some(existing_table) = target {
+                    queries.push(drop_replaced(existing_table, target_catalog));
+                }
+
+                // Create the clone
+                let copy = match method {
+                    CloneMethod::Shallow => "SHALLOW CLONE",
+                    CloneMethod::Deep => "DEEP CLONE",
+                    CloneMethod::Ctas => "AS SELECT * FROM",
+                };
+                queries.push(format!("CREATE TABLE {}.{}.{} {} {}.{}.{}",
+                    target_catalog, source.schema_name, source.name, copy,
+                    source.catalog_name, source.schema_name, source.name));
+                queries.extend(record_source_table_id(source, target_catalog));
+
+                Self {
+                    is_fast: *method == CloneMethod::Shallow,
+                    note: reason_note(reason, source),
+                    ..Self::new(queries, children())
+                }
+            }
+            Operation::Grant { object, principal, privileges } => Self::new(
+                vec![format!("GRANT {} ON {} {} TO `{}`",
+                    format_privileges(privileges), object.securable_type(),
+                    target_path(object, target_catalog), principal)],
+                vec![],
+            ),
+            Operation::Revoke { object, principal, privileges } => Self::new(
+                vec![format!("REVOKE {} ON {} {} FROM `{}`",
+                    format_privileges(privileges), object.securable_type(),
+                    target_path(object, target_catalog), principal)],
+                vec![],
+            ),
+            Operation::SetOwner { object, owner } => Self::new(
+                vec![format!("ALTER {} {} OWNER TO `{}`",
+                    object.securable_type(), target_path(object, target_catalog), owner)],
+                vec![],
+            ),
+            Operation::SetComment { object, comment } => {
+                let comment = comment.as_deref().map(sql_string).unwrap_or_else(|| "NULL".to_string());
+                Self::new(
+                    vec![format!("COMMENT ON {} {} IS {}",
+                        object.securable_type(), target_path(object, target_catalog), comment)],
+                    vec![],
+                )
+            }
+            Operation::SetTags { object, tags } => {
+                let tags = tags
+                    .iter()
+                    .map(|(key, value)| format!("{} = {}", sql_string(key), sql_string(value)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Self::new(vec![format!("{} SET TAGS ({})", alter_prefix(object, target_catalog), tags)], vec![])
+            }
+            Operation::UnsetTags { object, keys } => {
+                let keys = keys.iter().map(|key| sql_string(key)).collect::<Vec<_>>().join(", ");
+                Self::new(vec![format!("{} UNSET TAGS ({})", alter_prefix(object, target_catalog), keys)], vec![])
+            }
+            Operation::CreateVolume { source, target, location } => {
+                let mut queries = vec![];
+                // As with tables in `drop_replaced`, the drop may already have run.
+                if let Some(existing_volume) = target {
+                    queries.push(format!("DROP VOLUME IF EXISTS {}.{}.{}", target_catalog, existing_volume.schema_name, existing_volume.name));
+                }
+                match location {
+                    Some(location) => queries.push(format!("CREATE EXTERNAL VOLUME {}.{}.{} LOCATION {}",
+                        target_catalog, source.schema_name, source.name, sql_string(location))),
+                    None => queries.push(format!("CREATE VOLUME {}.{}.{}",
+                        target_catalog, source.schema_name, source.name)),
+                }
+                Self::new(queries, children())
+            }
+            // Dropping a volume removes its files as well
+            Operation::DropVolume(volume) => Self::new(
+                vec![format!("DROP VOLUME {}.{}.{}", target_catalog, volume.schema_name, volume.name)],
+                vec![],
+            ),
+            Operation::CopyVolumeFiles(volume) => Self {
+                is_fast: false,
+                copy_files: Some((
+                    format!("/Volumes/{}/{}/{}", volume.catalog_name, volume.schema_name, volume.name),
+                    format!("/Volumes/{}/{}/{}", target_catalog, volume.schema_name, volume.name),
+                )),
+                ..Self::new(vec![], vec![])
+            },
+            Operation::CreateFunction(function) => Self {
+                deferred: true,
+                ..Self::new(vec![create_function(function, target_catalog)], vec![])
+            },
+            Operation::DropFunction(function) => Self::new(
+                vec![format!("DROP FUNCTION {}.{}.{}", target_catalog, function.schema_name, function.name)],
+                vec![],
+            ),
+            Operation::CreateFromDefinition { source, target } => {
+                let mut queries = vec![];
+
+                // CREATE OR REPLACE cannot change the kind of an existing object
+                if let Some(existing_table) = target.filter(|t| t.kind() != source.kind()) {
+                    queries.push(drop_replaced(existing_table, target_catalog));
+                }
+
+                let create = match source.kind() {
+                    TableKind::MaterializedView => "CREATE OR REPLACE MATERIALIZED VIEW",
+                    TableKind::StreamingTable => "CREATE OR REFRESH STREAMING TABLE",
+                    _ => "CREATE OR REPLACE VIEW",
+                };
+                let definition = rewrite_catalog_references(
+                    source.view_definition.as_deref().unwrap_or_default(), &source.catalog_name, target_catalog);
+                queries.push(format!("{} {}.{}.{} AS {}",
+                    create, target_catalog, source.schema_name, source.name, definition));
+
+                Self {
+                    deferred: true,
+                    ..Self::new(queries, children())
+                }
+            }
+            Operation::SnapshotTable { source, target, spec, reason } => {
+                let mut queries = vec![];
+
+                if let Some(existing_table) = target.filter(|t| t.kind() != TableKind::Table) {
+                    queries.push(drop_replaced(existing_table, target_catalog));
+                }
+                let (columns, clause) = match spec {
+                    Some(spec) => (select_list(source, spec), spec.subset.as_ref().map(|s| format!(" {}", s.clause())).unwrap_or_default()),
+                    None => ("*".to_string(), String::new()),
+                };
+                queries.push(format!("CREATE OR REPLACE TABLE {}.{}.{} AS SELECT {} FROM {}.{}.{}{}",
+                    target_catalog, source.schema_name, source.name, columns,
+                    source.catalog_name, source.schema_name, source.name, clause));
+                queries.extend(record_source_table_id(source, target_catalog));
+
+                // Record the spec so the next run can tell a masked snapshot from a plain one.
+                let properties = spec.as_ref().map(|spec| spec.properties()).unwrap_or_default();
+                if !properties.is_empty() {
+                    queries.push(format!("ALTER TABLE {}.{}.{} SET TBLPROPERTIES ({})",
+                        target_catalog, source.schema_name, source.name,
+                        properties.iter().map(|(k, v)| format!("{} = {}", sql_string(k), sql_string(v))).collect::<Vec<_>>().join(", ")));
+                }
+                let unset: Vec<String> = [SUBSET_PROPERTY, MASKING_PROPERTY]
+                    .iter()
+                    .filter(|key| !properties.iter().any(|(k, _)| k == *key))
+                    .filter(|key| target.is_some_and(|t| t.properties.contains_key(**key)))
+                    .map(|key| sql_string(key))
+                    .collect();
+                if !unset.is_empty() {
+                    queries.push(format!("ALTER TABLE {}.{}.{} UNSET TBLPROPERTIES IF EXISTS ({})",
+                        target_catalog, source.schema_name, source.name, unset.join(", ")));
+                }
+
+                let mut notes: Vec<String> = reason_note(reason, source).into_iter().collect();
+                if let Some(spec) = spec.as_ref().filter(|spec| !spec.masked.is_empty()) {
+                    notes.push(format!("masked {}.{}.{}: {}",
+                        source.catalog_name, source.schema_name, source.name, spec.masking_summary()));
+                }
+
                 Self {
-                    query: None,
                     is_fast: false,
-                    children: diff_node.children.iter().map(|child| Self::from_diff_node(child, target_catalog)).collect(),
+                    note: match notes.is_empty() {
+                        true => None,
+                        false => Some(notes.join("\n-- ")),
+                    },
+                    ..Self::new(queries, children())
                 }
             }
-            Some(operation) => {
-                match operation {
-                    Operation::CreateCatalog(_catalog) => {
-                        Self {
-                            query: Some(format!("CREATE CATALOG {}", target_catalog)),
-                            is_fast: true,
-                            children: diff_node.children.iter().map(|child| Self::from_diff_node(child, target_catalog)).collect(),
-                        }
-                    }
-                    Operation::CreateSchema(schema) => {
-                        Self {
-                            query: Some(format!("CREATE SCHEMA {}.{}", target_catalog, schema._name)),
-                            is_fast: true,
-                            children: diff_node.children.iter().map(|child| Self::from_diff_node(child, target_catalog)).collect(),
-                        }
-                    }
-                    Operation::DropCatalog(catalog) => {
-                        Self {
-                            query: Some(format!("DROP CATALOG {} CASCADE", catalog.name)),
-                            is_fast: true,
-                            children: vec![], // no children because delete gets cascaded
-                        }
-                    }
-                    Operation::DropSchema(schema) => {
-                        Self {
-                            query: Some(format!("DROP SCHEMA {}.{} CASCADE", target_catalog, schema._name)),
-                            is_fast: true,
-                            children: vec![], // no children because delete gets cascaded
-                        }
-                    }
-                    Operation::DropTable(table) => {
-                        Self {
-                            query: Some(format!("DROP TABLE {}.{}.{}", target_catalog, table.schema_name, table.name)),
-                            is_fast: true,
-                            children: vec![], // no children because delete table is always leaf node
-                        }
-                    }
-                    Operation::CloneTable { source, target } => {
-                        let mut queries = vec![];
-                        
-                        // If there's an existing table to replace, drop it first
-                        if let Some(existing_table) = target {
-                            queries.push(format!("DROP TABLE {}.{}.{}", target_catalog, existing_table.schema_name, existing_table.name));
-                        }
-                        
-                        // Create the clone
-                        queries.push(format!("CREATE TABLE {}.{}.{} SHALLOW CLONE {}.{}.{}", 
-                            target_catalog, source.schema_name, source.name, 
-                            source.catalog_name, source.schema_name, source.name));
-                        
-                        Self {
-                            query: Some(queries.join(";\n")),
-                            is_fast: true,
-                            children: vec![],
-                        }
-                    }
+            Operation::SkipTable { table, reason } => Self {
+                note: Some(format!("skipped {}.{}.{}: {}", table.catalog_name, table.schema_name, table.name, reason)),
+                ..Self::new(vec![], vec![])
+            },
+            Operation::CopySelect { source, target, spec, reason } => {
+                let mut queries = vec![];
+
+                if let Some(existing_table) = target.filter(|t| t.kind() != TableKind::Table) {
+                    queries.push(drop_replaced(existing_table, target_catalog));
+                }
+                let clause = spec.subset.as_ref().map(|s| format!(" {}", s.clause())).unwrap_or_default();
+                queries.push(format!("CREATE OR REPLACE TABLE {}.{}.{} AS SELECT {} FROM {}.{}.{}{}",
+                    target_catalog, source.schema_name, source.name, select_list(source, spec),
+                    source.catalog_name, source.schema_name, source.name, clause));
+                queries.extend(record_source_table_id(source, target_catalog));
+
+                let mut properties = vec![(SOURCE_UPDATED_AT_PROPERTY, source.updated_at.timestamp_millis().to_string())];
+                properties.extend(spec.properties());
+                queries.push(format!("ALTER TABLE {}.{}.{} SET TBLPROPERTIES ({})",
+                    target_catalog, source.schema_name, source.name,
+                    properties.iter().map(|(k, v)| format!("{} = {}", sql_string(k), sql_string(v))).collect::<Vec<_>>().join(", ")));
+                let unset: Vec<String> = [SUBSET_PROPERTY, MASKING_PROPERTY]
+                    .iter()
+                    .filter(|key| !properties.iter().any(|(k, _)| k == *key))
+                    .filter(|key| target.is_some_and(|t| t.properties.contains_key(**key)))
+                    .map(|key| sql_string(key))
+                    .collect();
+                if !unset.is_empty() {
+                    queries.push(format!("ALTER TABLE {}.{}.{} UNSET TBLPROPERTIES IF EXISTS ({})",
+                        target_catalog, source.schema_name, source.name, unset.join(", ")));
                 }
+
+                let mut notes: Vec<String> = reason_note(reason, source).into_iter().collect();
+                if !spec.masked.is_empty() {
+                    notes.push(format!("masked {}.{}.{}: {}",
+                        source.catalog_name, source.schema_name, source.name, spec.masking_summary()));
+                }
+
+                Self {
+                    is_fast: false,
+                    note: match notes.is_empty() {
+                        true => None,
+                        false => Some(notes.join("\n-- ")),
+                    },
+                    ..Self::new(queries, children())
+                }
+            }
+        }
+    }
+
+    /// Orders the query tree for execution: parents before children, breadth
+    /// first, with deferred queries after everything else. Deferred queries are
+    /// ordered so views and functions come after the ones they reference. The returned
+    /// queries have no children.
+    pub fn flatten(self) -> Vec<Query> {
+        let mut ordered = vec![];
+        let mut queries = VecDeque::from([self]);
+        let mut deferred = VecDeque::new();
+        while let Some(mut query) = queries.pop_front() {
+            if query.deferred {
+                deferred.push_back(query);
+                continue;
+            }
+            queries.extend(std::mem::take(&mut query.children));
+            ordered.push(query);
+        }
+        let mut deferred = order_by_dependencies(deferred);
+        while let Some(mut query) = deferred.pop_front() {
+            deferred.extend(std::mem::take(&mut query.children));
+            ordered.push(query);
+        }
+        ordered
+    }
+}
+
+/// Orders deferred queries so each comes after the queries creating objects
+/// its statements reference, keeping their order otherwise. References are
+/// found by their unquoted three-part name, and a cycle keeps the original order.
+fn order_by_dependencies(queries: VecDeque<Query>) -> VecDeque<Query> {
+    let patterns: Vec<Option<Regex>> = queries
+        .iter()
+        .map(|query| {
+            let object = &query.entry.as_ref()?.object;
+            Some(Regex::new(&format!(r"(?i)(^|[^\w.`]){}($|[^\w.`])", regex::escape(object))).unwrap())
+        })
+        .collect();
+    let references: Vec<Vec<usize>> = queries
+        .iter()
+        .enumerate()
+        .map(|(index, query)| {
+            patterns
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .filter_map(|(other, pattern)| pattern.as_ref().map(|pattern| (other, pattern)))
+                .filter(|(_, pattern)| query.statements.iter().any(|statement| pattern.is_match(statement)))
+                .map(|(other, _)| other)
+                .collect()
+        })
+        .collect();
+
+    let mut queries: Vec<Option<Query>> = queries.into_iter().map(Some).collect();
+    let mut placed = vec![false; queries.len()];
+    let mut ordered = VecDeque::with_capacity(queries.len());
+    while ordered.len() < queries.len() {
+        let ready = (0..queries.len()).filter(|&index| !placed[index]);
+        let next = ready
+            .clone()
+            .find(|&index| references[index].iter().all(|&other| placed[other]))
+            .or_else(|| ready.clone().next())
+            .unwrap();
+        placed[next] = true;
+        ordered.push_back(queries[next].take().unwrap());
+    }
+    ordered
+}
+
+/// Renders flattened queries as a SQL script. Notes and file copies, which are
+/// not SQL, become comments.
+pub fn format_plan(queries: &[Query]) -> String {
+    let mut plan = String::new();
+    for query in queries {
+        if let Some(note) = &query.note {
+            plan.push_str(&format!("-- {}\n", note));
+        }
+        for statement in &query.statements {
+            plan.push_str(&format!("{};\n", statement));
+        }
+        if let Some((source, target)) = &query.copy_files {
+            plan.push_str(&format!("-- copy files from {} to {}\n", source, target));
+        }
+    }
+    plan
+}
+
+/// Path of `object` once it lives in `target_catalog`.
+pub fn target_path(object: &Object, target_catalog: &str) -> String {
+    match object {
+        Object::Catalog(_) => target_catalog.to_string(),
+        Object::Schema(schema) => format!("{}.{}", target_catalog, schema._name),
+        Object::Table(table) => format!("{}.{}.{}", target_catalog, table.schema_name, table.name),
+        Object::Column(table, column) => {
+            format!("{}.{}.{}.{}", target_catalog, table.schema_name, table.name, column.name)
+        }
+    }
+}
+
+/// `ALTER` clause addressing `object` in `target_catalog`. Columns are altered
+/// through their table.
+fn alter_prefix(object: &Object, target_catalog: &str) -> String {
+    match object {
+        Object::Column(table, column) => format!(
+            "ALTER TABLE {}.{}.{} ALTER COLUMN {}",
+            target_catalog, table.schema_name, table.name, column.name
+        ),
+        _ => format!("ALTER {} {}", object.securable_type(), target_path(object, target_catalog)),
+    }
+}
+
+/// Explanation printed above a copy whose reason is not obvious from the statement.
+fn reason_note(reason: &CloneReason, source: &Table) -> Option<String> {
+    match reason {
+        CloneReason::SchemaChanged(column_changes) => Some(format!("schema of {}.{}.{} changed:\n--   {}",
+            source.catalog_name, source.schema_name, source.name,
+            column_changes.iter().map(|c| c.to_string()).collect::<Vec<_>>().join("\n--   "))),
+        CloneReason::SourceRecreated { cloned_from } => Some(format!("{}.{}.{} was recreated since it was cloned from table id {}",
+            source.catalog_name, source.schema_name, source.name, cloned_from)),
+        _ => None,
+    }
+}
+
+/// Columns selected by a CTAS copy, with masked columns replaced by their transform.
+fn select_list(source: &Table, spec: &CopySpec) -> String {
+    if spec.masked.is_empty() {
+        return "*".to_string();
+    }
+    let mut columns: Vec<_> = source.columns.iter().collect();
+    columns.sort_by_key(|c| c.position);
+    columns
+        .into_iter()
+        .map(|column| match spec.masked.iter().find(|m| m.column.name == column.name) {
+            Some(masked) => {
+                let expression = match &masked.transform {
+                    MaskTransform::Hash => format!("sha2(CAST(`{}` AS STRING), 256)", column.name),
+                    MaskTransform::Null => format!("CAST(NULL AS {})", column.type_text),
+                    MaskTransform::Replace(expression) => expression.clone(),
+                };
+                format!("{} AS `{}`", expression, column.name)
+            }
+            None => format!("`{}`", column.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Records which source table a copy was made from, so a later run notices
+/// when the source is dropped and recreated.
+fn record_source_table_id(source: &Table, target_catalog: &str) -> Option<String> {
+    source.table_id.as_deref().map(|table_id| format!("ALTER TABLE {}.{}.{} SET TBLPROPERTIES ({} = {})",
+        target_catalog, source.schema_name, source.name, sql_string(SOURCE_TABLE_ID_PROPERTY), sql_string(table_id)))
+}
+
+/// Drops a table that is replaced. The drop may already have run when a query is
+/// retried or resumed after the statement that recreates the table failed.
+fn drop_replaced(table: &Table, target_catalog: &str) -> String {
+    format!("DROP {} IF EXISTS {}.{}.{}", drop_keyword(table), target_catalog, table.schema_name, table.name)
+}
+
+fn drop_keyword(table: &Table) -> &'static str {
+    match table.kind() {
+        TableKind::View => "VIEW",
+        TableKind::MaterializedView => "MATERIALIZED VIEW",
+        TableKind::Table | TableKind::StreamingTable => "TABLE",
+    }
+}
+
+fn format_parameters(parameters: &[FunctionParameter]) -> String {
+    parameters
+        .iter()
+        .map(|p| {
+            let mut sql = format!("{} {}", p.name, p.type_text);
+            if let Some(default) = &p.default {
+                sql.push_str(&format!(" DEFAULT {}", default));
+            }
+            if let Some(comment) = &p.comment {
+                sql.push_str(&format!(" COMMENT {}", sql_string(comment)));
+            }
+            sql
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `CREATE OR REPLACE FUNCTION` for `function` in `target_catalog`, with
+/// references to the source catalog in its body pointed at the target.
+fn create_function(function: &crate::unitycatalog::Function, target_catalog: &str) -> String {
+    let returns = match &function.returns {
+        FunctionReturns::Scalar(data_type) => data_type.clone(),
+        FunctionReturns::Table(columns) => format!("TABLE ({})", format_parameters(columns)),
+    };
+    let mut query = format!("CREATE OR REPLACE FUNCTION {}.{}.{}({})\nRETURNS {}",
+        target_catalog, function.schema_name, function.name, format_parameters(&function.parameters), returns);
+    if let Some(language) = &function.language {
+        query.push_str(&format!("\nLANGUAGE {}", language));
+    }
+    query.push_str(if function.deterministic { "\nDETERMINISTIC" } else { "\nNOT DETERMINISTIC" });
+    if let Some(comment) = &function.comment {
+        query.push_str(&format!("\nCOMMENT {}", sql_string(comment)));
+    }
+    let definition = rewrite_catalog_references(&function.definition, &function.catalog_name, target_catalog);
+    match function.language {
+        None => query.push_str(&format!("\nRETURN {}", definition)),
+        Some(_) => query.push_str(&format!("\nAS $$\n{}\n$$", definition)),
+    }
+    query
+}
+
+/// Single-quoted SQL string literal.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// The permissions API reports privileges as `USE_SCHEMA`, SQL expects `USE SCHEMA`.
+fn format_privileges(privileges: &[String]) -> String {
+    privileges
+        .iter()
+        .map(|p| p.replace('_', " "))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::unitycatalog::{Catalog, CatalogType, DiffOptions, DiffTree, Grants, PermissionOptions, Schema, Tags};
+    use chrono::{DateTime, Utc};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn at(day: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + day * 86_400, 0).unwrap()
+    }
+
+    pub(crate) fn table(catalog: &str, schema: &str, name: &str, table_type: &str, updated_day: i64) -> Table {
+        Table {
+            name: name.to_string(),
+            schema_name: schema.to_string(),
+            catalog_name: catalog.to_string(),
+            table_type: table_type.to_string(),
+            data_source_format: Some("DELTA".to_string()),
+            updated_at: at(updated_day),
+            _updated_by: "someone".to_string(),
+            table_id: Some(format!("{}-id", name)),
+            properties: HashMap::from([(SOURCE_TABLE_ID_PROPERTY.to_string(), format!("{}-id", name))]),
+            owner: None,
+            grants: Grants::new(),
+            comment: None,
+            tags: Tags::new(),
+            view_definition: (table_type == "VIEW").then(|| format!("SELECT * FROM {}.{}.base", catalog, schema)),
+            columns: vec![],
+            brief: false,
+        }
+    }
+
+    pub(crate) fn schema(catalog: &str, name: &str, tables: Vec<Table>) -> Schema {
+        Schema {
+            _name: name.to_string(),
+            _catalog_name: catalog.to_string(),
+            owner: None,
+            grants: Grants::new(),
+            comment: None,
+            tags: Tags::new(),
+            updated_at: None,
+            tables: tables.into_iter().map(|t| (t.name.clone(), t)).collect(),
+            volumes: BTreeMap::new(),
+            functions: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn catalog(name: &str, schemas: Vec<Schema>) -> Catalog {
+        Catalog {
+            name: name.to_string(),
+            catalog_type: CatalogType::Managed,
+            owner: None,
+            grants: Grants::new(),
+            comment: None,
+            tags: Tags::new(),
+            schemas: schemas.into_iter().map(|s| (s._name.clone(), s)).collect(),
+        }
+    }
+
+    fn plan(source: &Catalog, target: &Catalog) -> String {
+        let diff = source.diff(Some(target), &DiffOptions::default()).unwrap();
+        format_plan(&Query::from_diff_node(&diff, &target.name).flatten())
+    }
+
+    fn source(order: &[&str]) -> Catalog {
+        let tables = |schema_name: &str| {
+            order
+                .iter()
+                .map(|name| match *name {
+                    "v_orders" => table("prod", schema_name, name, "VIEW", 0),
+                    _ => table("prod", schema_name, name, "MANAGED", 5),
+                })
+                .collect()
+        };
+        catalog(
+            "prod",
+            vec![schema("prod", "sales", tables("sales")), schema("prod", "audit", tables("audit"))],
+        )
+    }
+
+    fn target() -> Catalog {
+        catalog(
+            "staging",
+            vec![
+                schema(
+                    "staging",
+                    "sales",
+                    vec![
+                        table("staging", "sales", "zz_legacy", "MANAGED", 0),
+                        table("staging", "sales", "orders", "MANAGED", 0),
+                        table("staging", "sales", "aa_legacy", "MANAGED", 0),
+                    ],
+                ),
+                schema("staging", "old", vec![]),
+            ],
+        )
+    }
+
+    #[test]
+    fn plan_is_sorted_with_drops_first() {
+        insta::assert_snapshot!(plan(&source(&["orders", "v_orders", "customers", "b", "a"]), &target()), @r"
+        DROP SCHEMA staging.old CASCADE;
+        CREATE SCHEMA staging.audit;
+        CREATE TABLE staging.audit.a SHALLOW CLONE prod.audit.a;
+        ALTER TABLE staging.audit.a SET TBLPROPERTIES ('dbsync.source_table_id' = 'a-id');
+        CREATE TABLE staging.audit.b SHALLOW CLONE prod.audit.b;
+        ALTER TABLE staging.audit.b SET TBLPROPERTIES ('dbsync.source_table_id' = 'b-id');
+        CREATE TABLE staging.audit.customers SHALLOW CLONE prod.audit.customers;
+        ALTER TABLE staging.audit.customers SET TBLPROPERTIES ('dbsync.source_table_id' = 'customers-id');
+        CREATE TABLE staging.audit.orders SHALLOW CLONE prod.audit.orders;
+        ALTER TABLE staging.audit.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+        DROP TABLE staging.sales.aa_legacy;
+        DROP TABLE staging.sales.zz_legacy;
+        CREATE TABLE staging.sales.a SHALLOW CLONE prod.sales.a;
+        ALTER TABLE staging.sales.a SET TBLPROPERTIES ('dbsync.source_table_id' = 'a-id');
+        CREATE TABLE staging.sales.b SHALLOW CLONE prod.sales.b;
+        ALTER TABLE staging.sales.b SET TBLPROPERTIES ('dbsync.source_table_id' = 'b-id');
+        CREATE TABLE staging.sales.customers SHALLOW CLONE prod.sales.customers;
+        ALTER TABLE staging.sales.customers SET TBLPROPERTIES ('dbsync.source_table_id' = 'customers-id');
+        DROP TABLE IF EXISTS staging.sales.orders;
+        CREATE TABLE staging.sales.orders SHALLOW CLONE prod.sales.orders;
+        ALTER TABLE staging.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+        CREATE OR REPLACE VIEW staging.audit.v_orders AS SELECT * FROM staging.audit.base;
+        CREATE OR REPLACE VIEW staging.sales.v_orders AS SELECT * FROM staging.sales.base;
+        ");
+    }
+
+    #[test]
+    fn plan_does_not_depend_on_insertion_order() {
+        let expected = plan(&source(&["orders", "v_orders", "customers", "b", "a"]), &target());
+        for order in [
+            ["a", "b", "customers", "orders", "v_orders"],
+            ["v_orders", "orders", "b", "customers", "a"],
+            ["customers", "a", "v_orders", "b", "orders"],
+        ] {
+            assert_eq!(plan(&source(&order), &target()), expected);
+        }
+    }
+
+    #[test]
+    fn ownership_is_synced_for_catalogs_schemas_and_tables() {
+        let mut source = catalog("prod", vec![schema("prod", "sales", vec![table("prod", "sales", "orders", "MANAGED", 0)])]);
+        source.owner = Some("data-platform".to_string());
+        let sales = source.schemas.get_mut("sales").unwrap();
+        sales.owner = Some("sales-team".to_string());
+        sales.tables.get_mut("orders").unwrap().owner = Some("sales-team".to_string());
+        let mut target = catalog("staging", vec![schema("staging", "sales", vec![table("staging", "sales", "orders", "MANAGED", 0)])]);
+        target.owner = Some("someone".to_string());
+        let options = DiffOptions {
+            permissions: Some(PermissionOptions {
+                sync_grants: true,
+                sync_ownership: true,
+                principal_mapping: HashMap::from([("data-platform".to_string(), "staging-admins".to_string())]),
+            }),
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &options).unwrap();
+        insta::assert_snapshot!(format_plan(&Query::from_diff_node(&diff, "staging").flatten()), @r"
+        ALTER CATALOG staging OWNER TO `staging-admins`;
+        ALTER SCHEMA staging.sales OWNER TO `sales-team`;
+        ALTER TABLE staging.sales.orders OWNER TO `sales-team`;
+        ");
+    }
+
+    #[test]
+    fn views_come_after_the_views_they_select_from() {
+        let mut report = table("prod", "sales", "a_report", "VIEW", 0);
+        report.view_definition = Some("SELECT * FROM prod.sales.z_base JOIN prod.sales.orders USING (id)".to_string());
+        let base = table("prod", "sales", "z_base", "VIEW", 0);
+        let source = catalog("prod", vec![schema("prod", "sales", vec![report, base])]);
+        let target = catalog("staging", vec![schema("staging", "sales", vec![])]);
+        insta::assert_snapshot!(plan(&source, &target), @r"
+        CREATE OR REPLACE VIEW staging.sales.z_base AS SELECT * FROM staging.sales.base;
+        CREATE OR REPLACE VIEW staging.sales.a_report AS SELECT * FROM staging.sales.z_base JOIN staging.sales.orders USING (id);
+        ");
+    }
+
+    #[test]
+    fn new_schemas_get_functions_only_when_they_are_synced() {
+        let mut sales = schema("prod", "sales", vec![]);
+        sales.functions.insert(
+            "mask_email".to_string(),
+            crate::unitycatalog::Function {
+                name: "mask_email".to_string(),
+                schema_name: "sales".to_string(),
+                catalog_name: "prod".to_string(),
+                parameters: vec![],
+                returns: FunctionReturns::Scalar("STRING".to_string()),
+                language: None,
+                deterministic: true,
+                comment: None,
+                definition: "'***'".to_string(),
+            },
+        );
+        let source = catalog("prod", vec![sales]);
+        let target = catalog("staging", vec![]);
+        assert_eq!(plan(&source, &target), "CREATE SCHEMA staging.sales;\n");
+
+        let options = DiffOptions {
+            functions: true,
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &options).unwrap();
+        let queries = Query::from_diff_node(&diff, "staging").flatten();
+        assert!(queries.iter().any(|q| q.statements.iter().any(|s| s.starts_with("CREATE OR REPLACE FUNCTION"))));
+    }
+
+    #[test]
+    fn ctas_copies_are_not_recopied_for_nullability_or_type_spelling() {
+        let column = |type_text: &str, nullable: bool| crate::unitycatalog::Column {
+            name: "id".to_string(),
+            type_text: type_text.to_string(),
+            nullable,
+            position: 0,
+            comment: None,
+            tags: Tags::new(),
+        };
+        let mut orders = table("lakehouse_pg", "sales", "orders", "FOREIGN", 0);
+        orders.columns = vec![column("varchar(255)", false)];
+        let mut copy = table("staging", "sales", "orders", "MANAGED", 0);
+        copy.columns = vec![column("string", true)];
+        copy.updated_at = Utc::now();
+        let source = catalog("lakehouse_pg", vec![schema("lakehouse_pg", "sales", vec![orders])]);
+        let target = catalog("staging", vec![schema("staging", "sales", vec![copy])]);
+
+        let ctas = DiffOptions {
+            clone_method: CloneMethod::Ctas,
+            ..DiffOptions::default()
+        };
+        assert!(source.diff(Some(&target), &ctas).is_none());
+        assert!(plan(&source, &target).contains("schema of lakehouse_pg.sales.orders changed"));
+    }
+
+    #[test]
+    fn snapshots_of_materialized_views_are_masked() {
+        let column = |name: &str, position: i64, tags: Tags| crate::unitycatalog::Column {
+            name: name.to_string(),
+            type_text: "string".to_string(),
+            nullable: true,
+            position,
+            comment: None,
+            tags,
+        };
+        let mut customers = table("prod", "sales", "customers_mv", "MATERIALIZED_VIEW", 0);
+        customers.columns = vec![
+            column("id", 0, Tags::new()),
+            column("email", 1, Tags::from([("pii".to_string(), "email".to_string())])),
+        ];
+        let source = catalog("prod", vec![schema("prod", "sales", vec![customers])]);
+        let target = catalog("staging", vec![schema("staging", "sales", vec![])]);
+        let options = DiffOptions {
+            materialized_views: crate::schema::DerivedTableStrategy::Snapshot,
+            masking: vec![crate::unitycatalog::MaskRule {
+                matcher: crate::unitycatalog::ColumnMatcher::Tag {
+                    key: "pii".to_string(),
+                    value: None,
+                },
+                transform: MaskTransform::Hash,
+            }],
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &options).unwrap();
+        insta::assert_snapshot!(format_plan(&Query::from_diff_node(&diff, "staging").flatten()), @r"
+        -- masked prod.sales.customers_mv: email: hash
+        CREATE OR REPLACE TABLE staging.sales.customers_mv AS SELECT `id`, sha2(CAST(`email` AS STRING), 256) AS `email` FROM prod.sales.customers_mv;
+        ALTER TABLE staging.sales.customers_mv SET TBLPROPERTIES ('dbsync.source_table_id' = 'customers_mv-id');
+        ALTER TABLE staging.sales.customers_mv SET TBLPROPERTIES ('dbsync.masking' = 'email: hash');
+        ");
+    }
+
+    #[test]
+    fn tags_and_comments_are_synced_without_recloning() {
+        let tags = |pairs: &[(&str, &str)]| -> Tags { pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect() };
+        let column = |comment: Option<&str>, tags: Tags| crate::unitycatalog::Column {
+            name: "email".to_string(),
+            type_text: "string".to_string(),
+            nullable: true,
+            position: 0,
+            comment: comment.map(str::to_string),
+            tags,
+        };
+        let mut orders = table("prod", "sales", "orders", "MANAGED", 0);
+        orders.comment = Some("One row per order".to_string());
+        orders.tags = tags(&[("domain", "sales")]);
+        orders.columns = vec![column(Some("Buyer's address"), tags(&[("pii", "email")]))];
+        let mut sales = schema("prod", "sales", vec![orders]);
+        sales.tags = tags(&[("domain", "sales")]);
+        let mut source = catalog("prod", vec![sales]);
+        source.comment = Some("Production".to_string());
+
+        let mut copy = table("staging", "sales", "orders", "MANAGED", 1);
+        copy.comment = Some("Old comment".to_string());
+        copy.tags = tags(&[("domain", "sales"), ("owner_team", "legacy")]);
+        copy.columns = vec![column(None, Tags::new())];
+        let mut sales = schema("staging", "sales", vec![copy]);
+        sales.tags = tags(&[("domain", "finance")]);
+        let target = catalog("staging", vec![sales]);
+
+        let options = DiffOptions {
+            metadata: Some(crate::unitycatalog::MetadataOptions {
+                comments: true,
+                tags: true,
+            }),
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &options).unwrap();
+        insta::assert_snapshot!(format_plan(&Query::from_diff_node(&diff, "staging").flatten()), @r"
+        COMMENT ON CATALOG staging IS 'Production';
+        ALTER SCHEMA staging.sales SET TAGS ('domain' = 'sales');
+        COMMENT ON TABLE staging.sales.orders IS 'One row per order';
+        ALTER TABLE staging.sales.orders UNSET TAGS ('owner_team');
+        COMMENT ON COLUMN staging.sales.orders.email IS 'Buyer\'s address';
+        ALTER TABLE staging.sales.orders ALTER COLUMN email SET TAGS ('pii' = 'email');
+        ");
+
+        let comments_only = DiffOptions {
+            metadata: Some(crate::unitycatalog::MetadataOptions {
+                comments: true,
+                tags: false,
+            }),
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &comments_only).unwrap();
+        let plan = format_plan(&Query::from_diff_node(&diff, "staging").flatten());
+        assert!(plan.contains("COMMENT ON") && !plan.contains("TAGS"), "{}", plan);
+    }
+
+    #[test]
+    fn volumes_are_created_replaced_and_dropped() {
+        let volume = |catalog: &str, name: &str, location: Option<&str>| crate::unitycatalog::Volume {
+            name: name.to_string(),
+            schema_name: "sales".to_string(),
+            catalog_name: catalog.to_string(),
+            volume_type: match location {
+                Some(_) => "EXTERNAL",
+                None => "MANAGED",
             }
+            .to_string(),
+            storage_location: location.map(str::to_string),
+            _updated_at: at(0),
+        };
+        let mut sales = schema("prod", "sales", vec![]);
+        for v in [
+            volume("prod", "fixtures", None),
+            volume("prod", "landing", Some("s3://prod-bucket/landing")),
+            volume("prod", "exports", Some("s3://prod-bucket/exports")),
+        ] {
+            sales.volumes.insert(v.name.clone(), v);
+        }
+        let source = catalog("prod", vec![sales]);
+        let mut sales = schema("staging", "sales", vec![]);
+        for v in [
+            volume("staging", "exports", Some("s3://prod-bucket/exports")),
+            volume("staging", "scratch", Some("s3://staging-bucket/scratch")),
+        ] {
+            sales.volumes.insert(v.name.clone(), v);
+        }
+        let target = catalog("staging", vec![sales]);
+
+        let options = DiffOptions {
+            volumes: Some(crate::unitycatalog::VolumeOptions {
+                managed: crate::schema::ManagedVolumeStrategy::CopyFiles,
+                location_mapping: HashMap::from([("s3://prod-bucket/".to_string(), "s3://staging-bucket/".to_string())]),
+            }),
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &options).unwrap();
+        insta::assert_snapshot!(format_plan(&Query::from_diff_node(&diff, "staging").flatten()), @r"
+        DROP VOLUME staging.sales.scratch;
+        DROP VOLUME IF EXISTS staging.sales.exports;
+        CREATE EXTERNAL VOLUME staging.sales.exports LOCATION 's3://staging-bucket/exports';
+        CREATE VOLUME staging.sales.fixtures;
+        CREATE EXTERNAL VOLUME staging.sales.landing LOCATION 's3://staging-bucket/landing';
+        -- copy files from /Volumes/prod/sales/fixtures to /Volumes/staging/sales/fixtures
+        ");
+
+        // Without volume options, volumes are left alone on both sides.
+        assert!(source.diff(Some(&target), &DiffOptions::default()).is_none());
+    }
+
+    #[test]
+    fn functions_come_after_the_functions_they_call() {
+        let function = |name: &str, definition: &str| crate::unitycatalog::Function {
+            name: name.to_string(),
+            schema_name: "sales".to_string(),
+            catalog_name: "prod".to_string(),
+            parameters: vec![],
+            returns: FunctionReturns::Scalar("STRING".to_string()),
+            language: None,
+            deterministic: true,
+            comment: None,
+            definition: definition.to_string(),
+        };
+        let mut report = table("prod", "sales", "a_report", "VIEW", 0);
+        report.view_definition = Some("SELECT prod.sales.a_label() AS label".to_string());
+        let mut sales = schema("prod", "sales", vec![report]);
+        for f in [function("a_label", "upper(prod.sales.z_clean())"), function("z_clean", "'clean'")] {
+            sales.functions.insert(f.name.clone(), f);
         }
+        let source = catalog("prod", vec![sales]);
+        let target = catalog("staging", vec![schema("staging", "sales", vec![])]);
+        let options = DiffOptions {
+            functions: true,
+            ..DiffOptions::default()
+        };
+        let diff = source.diff(Some(&target), &options).unwrap();
+        insta::assert_snapshot!(format_plan(&Query::from_diff_node(&diff, "staging").flatten()), @r"
+        CREATE OR REPLACE FUNCTION staging.sales.z_clean()
+        RETURNS STRING
+        DETERMINISTIC
+        RETURN 'clean';
+        CREATE OR REPLACE FUNCTION staging.sales.a_label()
+        RETURNS STRING
+        DETERMINISTIC
+        RETURN upper(staging.sales.z_clean());
+        CREATE OR REPLACE VIEW staging.sales.a_report AS SELECT staging.sales.a_label() AS label;
+        ");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn copies_only_unset_the_properties_their_target_has() {
+        let mut orders = table("prod", "sales", "orders", "MANAGED", 5);
+        orders.columns = vec![crate::unitycatalog::Column {
+            name: "email".to_string(),
+            type_text: "string".to_string(),
+            nullable: true,
+            position: 0,
+            comment: None,
+            tags: Tags::new(),
+        }];
+        let source = catalog("prod", vec![schema("prod", "sales", vec![orders.clone()])]);
+        let options = DiffOptions {
+            masking: vec![crate::unitycatalog::MaskRule {
+                matcher: crate::unitycatalog::ColumnMatcher::Name(crate::schema::glob_to_regex("email")),
+                transform: MaskTransform::Null,
+            }],
+            ..DiffOptions::default()
+        };
+        let plan = |target: &Catalog| {
+            let diff = source.diff(Some(target), &options).unwrap();
+            format_plan(&Query::from_diff_node(&diff, "staging").flatten())
+        };
+
+        let new = catalog("staging", vec![schema("staging", "sales", vec![])]);
+        insta::assert_snapshot!(plan(&new), @r"
+        -- masked prod.sales.orders: email: null
+        CREATE OR REPLACE TABLE staging.sales.orders AS SELECT CAST(NULL AS string) AS `email` FROM prod.sales.orders;
+        ALTER TABLE staging.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+        ALTER TABLE staging.sales.orders SET TBLPROPERTIES ('dbsync.source_updated_at' = '1700432000000', 'dbsync.masking' = 'email: null');
+        ");
+
+        let mut subset_copy = table("staging", "sales", "orders", "MANAGED", 0);
+        subset_copy.properties.insert(SUBSET_PROPERTY.to_string(), "TABLESAMPLE (1 PERCENT)".to_string());
+        let subset = catalog("staging", vec![schema("staging", "sales", vec![subset_copy])]);
+        assert!(plan(&subset).contains("UNSET TBLPROPERTIES IF EXISTS ('dbsync.subset')"));
+    }
+
+    #[test]
+    fn tables_are_recloned_when_their_source_was_recreated() {
+        let source = catalog("prod", vec![schema("prod", "sales", vec![table("prod", "sales", "orders", "MANAGED", 0)])]);
+        // The copy is newer than its source, but was cloned from a table that was since dropped.
+        let mut copy = table("staging", "sales", "orders", "MANAGED", 1);
+        copy.properties.insert(SOURCE_TABLE_ID_PROPERTY.to_string(), "dropped-orders-id".to_string());
+        let target = catalog("staging", vec![schema("staging", "sales", vec![copy.clone()])]);
+        insta::assert_snapshot!(plan(&source, &target), @r"
+        -- prod.sales.orders was recreated since it was cloned from table id dropped-orders-id
+        DROP TABLE IF EXISTS staging.sales.orders;
+        CREATE TABLE staging.sales.orders SHALLOW CLONE prod.sales.orders;
+        ALTER TABLE staging.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id');
+        ");
+
+        copy.properties.insert(SOURCE_TABLE_ID_PROPERTY.to_string(), "orders-id".to_string());
+        let target = catalog("staging", vec![schema("staging", "sales", vec![copy])]);
+        assert!(source.diff(Some(&target), &DiffOptions::default()).is_none());
+    }
+}
+
@@ -0,0 +1,1008 @@
+use crate::apply::{Outcome, Status};
+use crate::querygen::{target_path, Query};
+use crate::unitycatalog::{
+    CloneMethod, CloneReason, DiffNode, Object, Operation, Table, SOURCE_UPDATED_AT_PROPERTY,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Version of the JSON and JSON Lines output. Bumped whenever a field changes
+/// or goes away, adding fields keeps the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How an operation changes the target, used to colour and style it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Impact {
+    Create,
+    Replace,
+    Drop,
+    Attribute,
+    Skip,
+}
+
+impl Impact {
+    fn ansi_colour(self) -> &'static str {
+        match self {
+            Impact::Create => "\x1b[32m",
+            Impact::Replace => "\x1b[33m",
+            Impact::Drop => "\x1b[31m",
+            Impact::Attribute => "\x1b[36m",
+            Impact::Skip => "\x1b[2m",
+        }
+    }
+
+    fn css_class(self) -> &'static str {
+        match self {
+            Impact::Create => "create",
+            Impact::Replace => "replace",
+            Impact::Drop => "drop",
+            Impact::Attribute => "attribute",
+            Impact::Skip => "skip",
+        }
+    }
+}
+
+/// One operation of a plan, described for people rather than for the warehouse.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Nesting below the other entries of the plan.
+    pub depth: usize,
+    pub kind: Cow<'static, str>,
+    pub impact: Impact,
+    /// Path of the object in the target catalog.
+    pub object: String,
+    pub reason: Option<String>,
+    /// Size of the source table in bytes, when it has statistics.
+    pub size: Option<u64>,
+}
+
+/// Operations that sync one source catalog into one pinned catalog.
+#[derive(Debug)]
+pub struct Report {
+    pub source: String,
+    pub target: String,
+    pub entries: Vec<Entry>,
+}
+
+impl Report {
+    pub fn new(source: &str, target: &str, diff: Option<&DiffNode>) -> Self {
+        let mut entries = vec![];
+        if let Some(diff) = diff {
+            collect(diff, target, 0, &mut entries);
+        }
+        Self {
+            source: source.to_string(),
+            target: target.to_string(),
+            entries,
+        }
+    }
+
+    /// Entries that change the target. Objects that are intentionally not synced
+    /// are no drift.
+    pub fn drift(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|entry| entry.impact != Impact::Skip)
+    }
+
+    /// Number of entries per operation kind, in kind order.
+    pub fn counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in &self.entries {
+            *counts.entry(entry.kind.as_ref()).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// Entries in the same order as the tree, skipping the nodes that only group children.
+fn collect(node: &DiffNode, target_catalog: &str, depth: usize, entries: &mut Vec<Entry>) {
+    let child_depth = match &node.operation {
+        Some(operation) => {
+            entries.push(describe(operation, target_catalog, depth));
+            depth + 1
+        }
+        None => depth,
+    };
+    for child in &node.children {
+        collect(child, target_catalog, child_depth, entries);
+    }
+}
+
+pub fn describe(operation: &Operation, target_catalog: &str, depth: usize) -> Entry {
+    let table_path = |table: &Table| target_path(&Object::Table(table), target_catalog);
+    let new = || Some("new in source".to_string());
+    let missing = || Some("missing in source".to_string());
+    let (kind, impact, object, reason, size) = match operation {
+        Operation::CreateCatalog(_) => ("create catalog", Impact::Create, target_catalog.to_string(), new(), None),
+        Operation::CreateSchema(schema) => (
+            "create schema",
+            Impact::Create,
+            target_path(&Object::Schema(schema), target_catalog),
+            new(),
+            None,
+        ),
+        Operation::DropCatalog { catalog, reason } => {
+            ("drop catalog", Impact::Drop, catalog.name.clone(), Some(reason.clone()), None)
+        }
+        Operation::DropSchema(schema) => (
+            "drop schema",
+            Impact::Drop,
+            target_path(&Object::Schema(schema), target_catalog),
+            missing(),
+            None,
+        ),
+        Operation::DropTable(table) => ("drop table", Impact::Drop, table_path(table), missing(), None),
+        Operation::CloneTable { source, target, method, reason } => (
+            "clone",
+            match target {
+                Some(_) => Impact::Replace,
+                None => Impact::Create,
+            },
+            table_path(source),
+            Some(clone_reason(reason, source, *target, *method)),
+            source.size_bytes(),
+        ),
+        Operation::Grant { object, principal, privileges } => (
+            "grant",
+            Impact::Attribute,
+            target_path(object, target_catalog),
+            Some(format!("{} to {}", privileges.join(", "), principal)),
+            None,
+        ),
+        Operation::Revoke { object, principal, privileges } => (
+            "revoke",
+            Impact::Attribute,
+            target_path(object, target_catalog),
+            Some(format!("{} from {}", privileges.join(", "), principal)),
+            None,
+        ),
+        Operation::SetOwner { object, owner } => (
+            "set owner",
+            Impact::Attribute,
+            target_path(object, target_catalog),
+            Some(format!("owned by {} in source", owner)),
+            None,
+        ),
+        Operation::SetComment { object, comment } => (
+            "set comment",
+            Impact::Attribute,
+            target_path(object, target_catalog),
+            Some(match comment {
+                Some(_) => "comment differs from source".to_string(),
+                None => "no comment in source".to_string(),
+            }),
+            None,
+        ),
+        Operation::SetTags { object, tags } => (
+            "set tags",
+            Impact::Attribute,
+            target_path(object, target_catalog),
+            Some(tags.iter().map(|(key, value)| format!("{} = {}", key, value)).collect::<Vec<_>>().join(", ")),
+            None,
+        ),
+        Operation::UnsetTags { object, keys } => (
+            "unset tags",
+            Impact::Attribute,
+            target_path(object, target_catalog),
+            Some(format!("{} missing in source", keys.join(", "))),
+            None,
+        ),
+        Operation::CreateVolume { source, target, .. } => (
+            "create volume",
+            match target {
+                Some(_) => Impact::Replace,
+                None => Impact::Create,
+            },
+            format!("{}.{}.{}", target_catalog, source.schema_name, source.name),
+            match target {
+                Some(_) => Some("type or location differs from source".to_string()),
+                None => new(),
+            },
+            None,
+        ),
+        Operation::DropVolume(volume) => (
+            "drop volume",
+            Impact::Drop,
+            format!("{}.{}.{}", target_catalog, volume.schema_name, volume.name),
+            missing(),
+            None,
+        ),
+        Operation::CopyVolumeFiles(volume) => (
+            "copy files",
+            Impact::Create,
+            format!("{}.{}.{}", target_catalog, volume.schema_name, volume.name),
+            Some("files of a managed volume".to_string()),
+            None,
+        ),
+        Operation::CreateFunction(function) => (
+            "create function",
+            Impact::Replace,
+            format!("{}.{}.{}", target_catalog, function.schema_name, function.name),
+            Some("new or changed in source".to_string()),
+            None,
+        ),
+        Operation::DropFunction(function) => (
+            "drop function",
+            Impact::Drop,
+            format!("{}.{}.{}", target_catalog, function.schema_name, function.name),
+            missing(),
+            None,
+        ),
+        Operation::CreateFromDefinition { source, target } => (
+            "recreate",
+            match target {
+                Some(_) => Impact::Replace,
+                None => Impact::Create,
+            },
+            table_path(source),
+            match target {
+                None => new(),
+                Some(target) if target.kind() == source.kind() => Some("definition changed".to_string()),
+                Some(target) => Some(format!("target is a {}", target.table_type)),
+            },
+            None,
+        ),
+        Operation::SnapshotTable { source, target, spec, reason } => {
+            let mut details = vec![clone_reason(reason, source, *target, CloneMethod::Ctas)];
+            if let Some(spec) = spec {
+                details.extend(spec.subset.as_ref().map(|subset| subset.clause()));
+                if !spec.masked.is_empty() {
+                    details.push(format!("masked {}", spec.masking_summary()));
+                }
+            }
+            (
+                "snapshot",
+                match target {
+                    Some(_) => Impact::Replace,
+                    None => Impact::Create,
+                },
+                table_path(source),
+                Some(details.join("; ")),
+                source.size_bytes(),
+            )
+        }
+        Operation::SkipTable { table, reason } => ("skip", Impact::Skip, table_path(table), Some(reason.clone()), None),
+        Operation::CopySelect { source, target, spec, reason } => {
+            let mut details = vec![clone_reason(reason, source, *target, CloneMethod::Ctas)];
+            if let Some(subset) = &spec.subset {
+                details.push(subset.clause());
+            }
+            if !spec.masked.is_empty() {
+                details.push(format!("masked {}", spec.masking_summary()));
+            }
+            (
+                "copy subset",
+                match target {
+                    Some(_) => Impact::Replace,
+                    None => Impact::Create,
+                },
+                table_path(source),
+                Some(details.join("; ")),
+                source.size_bytes(),
+            )
+        }
+    };
+    Entry {
+        depth,
+        kind: Cow::Borrowed(kind),
+        impact,
+        object,
+        reason,
+        size,
+    }
+}
+
+fn clone_reason(reason: &CloneReason, source: &Table, target: Option<&Table>, method: CloneMethod) -> String {
+    match reason {
+        CloneReason::New => "new in source".to_string(),
+        CloneReason::Stale => {
+            // Subset copies record when the source was copied, other copies are dated by themselves.
+            let copied_at = target.and_then(|t| {
+                t.properties
+                    .get(SOURCE_UPDATED_AT_PROPERTY)
+                    .and_then(|millis| millis.parse().ok())
+                    .and_then(DateTime::from_timestamp_millis)
+                    .or(Some(t.updated_at))
+            });
+            match copied_at {
+                Some(copied_at) if method == CloneMethod::Ctas && source.updated_at <= copied_at => {
+                    format!("copy is {} old", format_age(Utc::now() - copied_at))
+                }
+                Some(copied_at) => format!("stale by {}", format_age(source.updated_at - copied_at)),
+                None => "stale".to_string(),
+            }
+        }
+        CloneReason::KindChanged => match target {
+            Some(target) => format!("target is a {}", target.table_type),
+            None => "target is not a table".to_string(),
+        },
+        CloneReason::SchemaChanged(changes) => format!(
+            "columns changed: {}",
+            changes.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", ")
+        ),
+        CloneReason::SourceRecreated { cloned_from } => format!("source recreated since copied from table id {}", cloned_from),
+        CloneReason::CopySpecChanged => "subset or masking changed".to_string(),
+    }
+}
+
+fn format_age(age: TimeDelta) -> String {
+    match age {
+        age if age.num_days() > 0 => format!("{}d", age.num_days()),
+        age if age.num_hours() > 0 => format!("{}h", age.num_hours()),
+        age => format!("{}m", age.num_minutes().max(0)),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
+
+/// Reason and size, as shown after an entry.
+fn details(entry: &Entry) -> Vec<String> {
+    entry.reason.iter().cloned().chain(entry.size.map(format_size)).collect()
+}
+
+fn summary(report: &Report) -> String {
+    match report.entries.is_empty() {
+        true => "no changes".to_string(),
+        false => report
+            .counts()
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Tree of operations per pinned catalog, followed by counts per operation kind.
+pub fn render_terminal(reports: &[Report], colour: bool) -> String {
+    let paint = |text: &str, code: &str| match colour {
+        true => format!("{}{}\x1b[0m", code, text),
+        false => text.to_string(),
+    };
+    let mut out = String::new();
+    for report in reports {
+        out.push_str(&paint(&format!("{} -> {}", report.source, report.target), "\x1b[1m"));
+        out.push('\n');
+        for entry in &report.entries {
+            out.push_str(&"│ ".repeat(entry.depth));
+            out.push_str("├─ ");
+            out.push_str(&paint(&entry.kind, entry.impact.ansi_colour()));
+            out.push(' ');
+            out.push_str(&entry.object);
+            let details = details(entry);
+            if !details.is_empty() {
+                out.push_str(&paint(&format!(" ({})", details.join(", ")), "\x1b[2m"));
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!("Summary: {}\n\n", summary(report)));
+    }
+    out
+}
+
+fn escape_markdown(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Counts and operations per pinned catalog as Markdown tables, for pull requests.
+pub fn render_markdown(reports: &[Report]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        out.push_str(&format!("### `{}` → `{}`\n\n", report.source, report.target));
+        if report.entries.is_empty() {
+            out.push_str("No changes.\n\n");
+            continue;
+        }
+        out.push_str("| Operation | Count |\n|---|---:|\n");
+        for (kind, count) in report.counts() {
+            out.push_str(&format!("| {} | {} |\n", kind, count));
+        }
+        out.push_str("\n| Operation | Object | Reason | Size |\n|---|---|---|---:|\n");
+        for entry in &report.entries {
+            out.push_str(&format!(
+                "| {}{} | `{}` | {} | {} |\n",
+                "↳ ".repeat(entry.depth),
+                entry.kind,
+                escape_markdown(&entry.object),
+                escape_markdown(entry.reason.as_deref().unwrap_or_default()),
+                entry.size.map(format_size).unwrap_or_default()
+            ));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// One line per entry that drifted, as shown by `check` and in JUnit failures.
+fn drift_lines(report: &Report) -> Vec<String> {
+    report
+        .drift()
+        .map(|entry| {
+            let details = details(entry);
+            match details.is_empty() {
+                true => format!("{} {}", entry.kind, entry.object),
+                false => format!("{} {} ({})", entry.kind, entry.object, details.join(", ")),
+            }
+        })
+        .collect()
+}
+
+fn drift_summary(report: &Report) -> String {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in report.drift() {
+        *counts.entry(entry.kind.as_ref()).or_default() += 1;
+    }
+    counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Exit code of `check` when a pinned catalog drifted.
+pub const EXIT_DRIFTED: i32 = 2;
+
+/// Exit code of `check`: `0` when every pinned catalog is in sync, [`EXIT_DRIFTED`] otherwise.
+pub fn check_exit_code(reports: &[Report]) -> i32 {
+    match reports.iter().all(|report| report.drift().next().is_none()) {
+        true => 0,
+        false => EXIT_DRIFTED,
+    }
+}
+
+/// Whether each pinned catalog is in sync, listing what drifted otherwise.
+pub fn render_check(reports: &[Report]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        let lines = drift_lines(report);
+        if lines.is_empty() {
+            out.push_str(&format!("{} -> {}: in sync\n", report.source, report.target));
+            continue;
+        }
+        out.push_str(&format!("{} -> {}: drifted, {}\n", report.source, report.target, drift_summary(report)));
+        for line in lines {
+            out.push_str(&format!("  {}\n", line));
+        }
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+th,td{border:1px solid #ddd;padding:4px 8px;text-align:left;vertical-align:top}\
+th{background:#f4f4f4}td.size{text-align:right;white-space:nowrap}code{font-size:90%}\
+.create{color:#1a7f37}.replace{color:#9a6700}.drop{color:#cf222e}.attribute{color:#0969da}.skip{color:#6e7781}";
+
+/// Self-contained HTML page with the same content as the Markdown report.
+pub fn render_html(reports: &[Report]) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>dbsync plan</title>\n");
+    out.push_str(&format!("<style>{}</style>\n</head>\n<body>\n<h1>dbsync plan</h1>\n", HTML_STYLE));
+    for report in reports {
+        out.push_str(&format!(
+            "<h2><code>{}</code> → <code>{}</code></h2>\n",
+            escape_html(&report.source),
+            escape_html(&report.target)
+        ));
+        if report.entries.is_empty() {
+            out.push_str("<p>No changes.</p>\n");
+            continue;
+        }
+        out.push_str("<table>\n<tr><th>Operation</th><th>Count</th></tr>\n");
+        for (kind, count) in report.counts() {
+            out.push_str(&format!("<tr><td>{}</td><td>{}</td></tr>\n", kind, count));
+        }
+        out.push_str("</table>\n<table>\n<tr><th>Operation</th><th>Object</th><th>Reason</th><th>Size</th></tr>\n");
+        for entry in &report.entries {
+            out.push_str(&format!(
+                "<tr><td class=\"{}\" style=\"padding-left:{}em\">{}</td><td><code>{}</code></td><td>{}</td><td class=\"size\">{}</td></tr>\n",
+                entry.impact.css_class(),
+                0.5 + 1.5 * entry.depth as f64,
+                entry.kind,
+                escape_html(&entry.object),
+                escape_html(entry.reason.as_deref().unwrap_or_default()),
+                entry.size.map(format_size).unwrap_or_default()
+            ));
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Flattened queries that sync one source catalog into one pinned catalog, with
+/// their outcomes once applied.
+#[derive(Serialize, Deserialize)]
+pub struct Run {
+    pub source: String,
+    pub target: String,
+    pub queries: Vec<Query>,
+    /// One per query, empty while the queries are only planned.
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Run {
+    pub fn failed(&self) -> bool {
+        self.outcomes.iter().any(|outcome| outcome.status == Status::Failed)
+    }
+
+    /// Number of operations per status, all planned until the run is applied.
+    pub fn counts(&self) -> BTreeMap<Status, usize> {
+        let mut counts = BTreeMap::new();
+        for (index, query) in self.queries.iter().enumerate() {
+            if query.entry.is_some() {
+                let status = self.outcomes.get(index).map_or(Status::Planned, |outcome| outcome.status);
+                *counts.entry(status).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Operations per status, such as `3 succeeded, 1 failed`.
+    pub fn summary(&self) -> String {
+        let counts = self.counts();
+        match counts.is_empty() {
+            true => "no changes".to_string(),
+            false => counts
+                .iter()
+                .map(|(status, count)| format!("{} {}", count, status))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CopyFiles<'a> {
+    source: &'a str,
+    target: &'a str,
+}
+
+/// One operation in the JSON and JSON Lines output.
+#[derive(Serialize)]
+struct OperationRecord<'a> {
+    /// Position in execution order within the run.
+    sequence: usize,
+    kind: &'a str,
+    object: &'a str,
+    reason: Option<&'a str>,
+    size_bytes: Option<u64>,
+    sql: &'a [String],
+    copy_files: Option<CopyFiles<'a>>,
+    status: Status,
+    duration_ms: Option<u128>,
+    error: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct RunRecord<'a> {
+    source: &'a str,
+    target: &'a str,
+    operations: Vec<OperationRecord<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct Document<'a> {
+    schema_version: u32,
+    runs: Vec<RunRecord<'a>>,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    schema_version: u32,
+    source: &'a str,
+    target: &'a str,
+    #[serde(flatten)]
+    operation: OperationRecord<'a>,
+}
+
+/// Operations of a run, leaving out the queries that only group others.
+fn operations(run: &Run) -> Vec<OperationRecord<'_>> {
+    let mut sequence = 0;
+    let mut operations = vec![];
+    for (index, query) in run.queries.iter().enumerate() {
+        let Some(entry) = &query.entry else {
+            continue;
+        };
+        let outcome = run.outcomes.get(index);
+        let status = match outcome {
+            Some(outcome) => outcome.status,
+            None if entry.impact == Impact::Skip => Status::Skipped,
+            None => Status::Planned,
+        };
+        sequence += 1;
+        operations.push(OperationRecord {
+            sequence,
+            kind: &entry.kind,
+            object: &entry.object,
+            reason: entry.reason.as_deref(),
+            size_bytes: entry.size,
+            sql: &query.statements,
+            copy_files: query
+                .copy_files
+                .as_ref()
+                .map(|(source, target)| CopyFiles { source, target }),
+            status,
+            duration_ms: outcome.and_then(|o| o.duration).map(|d| d.as_millis()),
+            error: outcome.and_then(|o| o.error.as_deref()),
+        });
+    }
+    operations
+}
+
+/// The document `render_json` writes, for embedding in other JSON.
+pub fn json_document(runs: &[Run]) -> Document<'_> {
+    Document {
+        schema_version: SCHEMA_VERSION,
+        runs: runs
+            .iter()
+            .map(|run| RunRecord {
+                source: &run.source,
+                target: &run.target,
+                operations: operations(run),
+            })
+            .collect(),
+    }
+}
+
+/// A single JSON document with every run and its operations.
+pub fn render_json(runs: &[Run]) -> String {
+    serde_json::to_string_pretty(&json_document(runs)).unwrap() + "\n"
+}
+
+/// One JSON object per operation and line, each carrying its run and the schema version.
+pub fn render_jsonl(runs: &[Run]) -> String {
+    let mut out = String::new();
+    for run in runs {
+        for operation in operations(run) {
+            let line = Line {
+                schema_version: SCHEMA_VERSION,
+                source: &run.source,
+                target: &run.target,
+                operation,
+            };
+            out.push_str(&serde_json::to_string(&line).unwrap());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// JUnit XML with one test case per pinned catalog, failing when it drifted.
+pub fn render_junit(reports: &[Report]) -> String {
+    let failures = reports.iter().filter(|report| report.drift().next().is_some()).count();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites name=\"dbsync check\" tests=\"{}\" failures=\"{}\">\n",
+        reports.len(),
+        failures
+    ));
+    out.push_str(&format!(
+        "  <testsuite name=\"dbsync check\" tests=\"{}\" failures=\"{}\" errors=\"0\">\n",
+        reports.len(),
+        failures
+    ));
+    for report in reports {
+        out.push_str(&format!(
+            "    <testcase classname=\"{}\" name=\"{}\"",
+            escape_html(&report.source),
+            escape_html(&report.target)
+        ));
+        let lines = drift_lines(report);
+        if lines.is_empty() {
+            out.push_str("/>\n");
+            continue;
+        }
+        out.push_str(&format!(
+            ">\n      <failure type=\"drift\" message=\"{}\">{}</failure>\n    </testcase>\n",
+            escape_html(&drift_summary(report)),
+            escape_html(&lines.join("\n"))
+        ));
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::querygen::tests::{catalog, schema, table};
+    use crate::unitycatalog::{DiffOptions, DiffTree, TOTAL_SIZE_PROPERTY};
+    use std::time::Duration;
+
+    /// A plan for staging with a new, a stale and a dropped table, a skipped
+    /// streaming table and a view, and one for qa that is in sync.
+    fn reports() -> Vec<Report> {
+        let mut orders = table("prod", "sales", "orders", "MANAGED", 3);
+        orders.properties.insert(TOTAL_SIZE_PROPERTY.to_string(), "1572864".to_string());
+        let source = catalog(
+            "prod",
+            vec![schema(
+                "prod",
+                "sales",
+                vec![
+                    orders,
+                    table("prod", "sales", "customers", "MANAGED", 0),
+                    table("prod", "sales", "events", "STREAMING_TABLE", 0),
+                    table("prod", "sales", "v_orders", "VIEW", 0),
+                ],
+            )],
+        );
+        let target = catalog(
+            "staging",
+            vec![schema(
+                "staging",
+                "sales",
+                vec![
+                    table("staging", "sales", "orders", "MANAGED", 0),
+                    table("staging", "sales", "legacy|old", "MANAGED", 0),
+                ],
+            )],
+        );
+        let diff = source.diff(Some(&target), &DiffOptions::default());
+        vec![Report::new("prod", "staging", diff.as_ref()), Report::new("prod", "qa", None)]
+    }
+
+    /// The staging plan after applying it stopped at its second operation.
+    fn runs() -> Vec<Run> {
+        let source = catalog(
+            "prod",
+            vec![schema(
+                "prod",
+                "sales",
+                vec![
+                    table("prod", "sales", "customers", "MANAGED", 0),
+                    table("prod", "sales", "orders", "MANAGED", 0),
+                ],
+            )],
+        );
+        let target = catalog("staging", vec![]);
+        let diff = source.diff(Some(&target), &DiffOptions::default()).unwrap();
+        let queries = Query::from_diff_node(&diff, "staging").flatten();
+        let mut outcomes = vec![Outcome::new(Status::Skipped); queries.len()];
+        let operations: Vec<usize> = (0..queries.len()).filter(|&i| queries[i].entry.is_some()).collect();
+        outcomes[operations[0]] = Outcome {
+            duration: Some(Duration::from_millis(120)),
+            ..Outcome::new(Status::Succeeded)
+        };
+        outcomes[operations[1]] = Outcome {
+            duration: Some(Duration::from_millis(80)),
+            error: Some("PERMISSION_DENIED".to_string()),
+            ..Outcome::new(Status::Failed)
+        };
+        outcomes[operations[2]] = Outcome::new(Status::NotRun);
+        vec![Run {
+            source: "prod".to_string(),
+            target: "staging".to_string(),
+            queries,
+            outcomes,
+        }]
+    }
+
+    #[test]
+    fn terminal() {
+        insta::assert_snapshot!(render_terminal(&reports(), false), @r"
+        prod -> staging
+        ├─ drop table staging.sales.legacy|old (missing in source)
+        ├─ clone staging.sales.customers (new in source)
+        ├─ skip staging.sales.events (STREAMING_TABLE is not synced)
+        ├─ clone staging.sales.orders (stale by 3d, 1.5 MB)
+        ├─ recreate staging.sales.v_orders (new in source)
+        Summary: 2 clone, 1 drop table, 1 recreate, 1 skip
+
+        prod -> qa
+        Summary: no changes
+        ");
+    }
+
+    #[test]
+    fn terminal_colours_operations_by_impact() {
+        let out = render_terminal(&reports(), true);
+        assert!(out.contains("\x1b[31mdrop table\x1b[0m"), "{}", out);
+        assert!(out.contains("\x1b[2mskip\x1b[0m"), "{}", out);
+    }
+
+    #[test]
+    fn markdown() {
+        insta::assert_snapshot!(render_markdown(&reports()), @r"
+        ### `prod` → `staging`
+
+        | Operation | Count |
+        |---|---:|
+        | clone | 2 |
+        | drop table | 1 |
+        | recreate | 1 |
+        | skip | 1 |
+
+        | Operation | Object | Reason | Size |
+        |---|---|---|---:|
+        | drop table | `staging.sales.legacy\|old` | missing in source |  |
+        | clone | `staging.sales.customers` | new in source |  |
+        | skip | `staging.sales.events` | STREAMING_TABLE is not synced |  |
+        | clone | `staging.sales.orders` | stale by 3d | 1.5 MB |
+        | recreate | `staging.sales.v_orders` | new in source |  |
+
+        ### `prod` → `qa`
+
+        No changes.
+        ");
+    }
+
+    #[test]
+    fn html() {
+        insta::assert_snapshot!(render_html(&reports()), @r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+        <meta charset="utf-8">
+        <title>dbsync plan</title>
+        <style>body{font-family:system-ui,sans-serif;margin:2em;color:#222}table{border-collapse:collapse;margin-bottom:1.5em}th,td{border:1px solid #ddd;padding:4px 8px;text-align:left;vertical-align:top}th{background:#f4f4f4}td.size{text-align:right;white-space:nowrap}code{font-size:90%}.create{color:#1a7f37}.replace{color:#9a6700}.drop{color:#cf222e}.attribute{color:#0969da}.skip{color:#6e7781}</style>
+        </head>
+        <body>
+        <h1>dbsync plan</h1>
+        <h2><code>prod</code> → <code>staging</code></h2>
+        <table>
+        <tr><th>Operation</th><th>Count</th></tr>
+        <tr><td>clone</td><td>2</td></tr>
+        <tr><td>drop table</td><td>1</td></tr>
+        <tr><td>recreate</td><td>1</td></tr>
+        <tr><td>skip</td><td>1</td></tr>
+        </table>
+        <table>
+        <tr><th>Operation</th><th>Object</th><th>Reason</th><th>Size</th></tr>
+        <tr><td class="drop" style="padding-left:0.5em">drop table</td><td><code>staging.sales.legacy|old</code></td><td>missing in source</td><td class="size"></td></tr>
+        <tr><td class="create" style="padding-left:0.5em">clone</td><td><code>staging.sales.customers</code></td><td>new in source</td><td class="size"></td></tr>
+        <tr><td class="skip" style="padding-left:0.5em">skip</td><td><code>staging.sales.events</code></td><td>STREAMING_TABLE is not synced</td><td class="size"></td></tr>
+        <tr><td class="replace" style="padding-left:0.5em">clone</td><td><code>staging.sales.orders</code></td><td>stale by 3d</td><td class="size">1.5 MB</td></tr>
+        <tr><td class="create" style="padding-left:0.5em">recreate</td><td><code>staging.sales.v_orders</code></td><td>new in source</td><td class="size"></td></tr>
+        </table>
+        <h2><code>prod</code> → <code>qa</code></h2>
+        <p>No changes.</p>
+        </body>
+        </html>
+        "#);
+    }
+
+    #[test]
+    fn check() {
+        insta::assert_snapshot!(render_check(&reports()), @r"
+        prod -> staging: drifted, 2 clone, 1 drop table, 1 recreate
+          drop table staging.sales.legacy|old (missing in source)
+          clone staging.sales.customers (new in source)
+          clone staging.sales.orders (stale by 3d, 1.5 MB)
+          recreate staging.sales.v_orders (new in source)
+        prod -> qa: in sync
+        ");
+    }
+
+    #[test]
+    fn junit() {
+        insta::assert_snapshot!(render_junit(&reports()), @r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <testsuites name="dbsync check" tests="2" failures="1">
+          <testsuite name="dbsync check" tests="2" failures="1" errors="0">
+            <testcase classname="prod" name="staging">
+              <failure type="drift" message="2 clone, 1 drop table, 1 recreate">drop table staging.sales.legacy|old (missing in source)
+        clone staging.sales.customers (new in source)
+        clone staging.sales.orders (stale by 3d, 1.5 MB)
+        recreate staging.sales.v_orders (new in source)</failure>
+            </testcase>
+            <testcase classname="prod" name="qa"/>
+          </testsuite>
+        </testsuites>
+        "#);
+    }
+
+    #[test]
+    fn check_exits_with_drifted_only_when_a_pinned_catalog_drifted() {
+        let reports = reports();
+        assert_eq!(check_exit_code(&reports), EXIT_DRIFTED);
+        assert_eq!(check_exit_code(&reports[1..]), 0);
+        assert_eq!(check_exit_code(&[]), 0);
+
+        // Objects that are intentionally not synced are no drift.
+        let source = catalog("prod", vec![schema("prod", "sales", vec![table("prod", "sales", "events", "STREAMING_TABLE", 0)])]);
+        let target = catalog("staging", vec![schema("staging", "sales", vec![])]);
+        let diff = source.diff(Some(&target), &DiffOptions::default());
+        assert_eq!(check_exit_code(&[Report::new("prod", "staging", diff.as_ref())]), 0);
+    }
+
+    #[test]
+    fn json() {
+        insta::assert_snapshot!(render_json(&runs()), @r#"
+        {
+          "schema_version": 1,
+          "runs": [
+            {
+              "source": "prod",
+              "target": "staging",
+              "operations": [
+                {
+                  "sequence": 1,
+                  "kind": "create schema",
+                  "object": "staging.sales",
+                  "reason": "new in source",
+                  "size_bytes": null,
+                  "sql": [
+                    "CREATE SCHEMA staging.sales"
+                  ],
+                  "copy_files": null,
+                  "status": "succeeded",
+                  "duration_ms": 120,
+                  "error": null
+                },
+                {
+                  "sequence": 2,
+                  "kind": "clone",
+                  "object": "staging.sales.customers",
+                  "reason": "new in source",
+                  "size_bytes": null,
+                  "sql": [
+                    "CREATE TABLE staging.sales.customers SHALLOW CLONE prod.sales.customers",
+                    "ALTER TABLE staging.sales.customers SET TBLPROPERTIES ('dbsync.source_table_id' = 'customers-id')"
+                  ],
+                  "copy_files": null,
+                  "status": "failed",
+                  "duration_ms": 80,
+                  "error": "PERMISSION_DENIED"
+                },
+                {
+                  "sequence": 3,
+                  "kind": "clone",
+                  "object": "staging.sales.orders",
+                  "reason": "new in source",
+                  "size_bytes": null,
+                  "sql": [
+                    "CREATE TABLE staging.sales.orders SHALLOW CLONE prod.sales.orders",
+                    "ALTER TABLE staging.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id')"
+                  ],
+                  "copy_files": null,
+                  "status": "not_run",
+                  "duration_ms": null,
+                  "error": null
+                }
+              ]
+            }
+          ]
+        }
+        "#);
+    }
+
+    #[test]
+    fn jsonl() {
+        insta::assert_snapshot!(render_jsonl(&runs()), @r#"
+        {"schema_version":1,"source":"prod","target":"staging","sequence":1,"kind":"create schema","object":"staging.sales","reason":"new in source","size_bytes":null,"sql":["CREATE SCHEMA staging.sales"],"copy_files":null,"status":"succeeded","duration_ms":120,"error":null}
+        {"schema_version":1,"source":"prod","target":"staging","sequence":2,"kind":"clone","object":"staging.sales.customers","reason":"new in source","size_bytes":null,"sql":["CREATE TABLE staging.sales.customers SHALLOW CLONE prod.sales.customers","ALTER TABLE staging.sales.customers SET TBLPROPERTIES ('dbsync.source_table_id' = 'customers-id')"],"copy_files":null,"status":"failed","duration_ms":80,"error":"PERMISSION_DENIED"}
+        {"schema_version":1,"source":"prod","target":"staging","sequence":3,"kind":"clone","object":"staging.sales.orders","reason":"new in source","size_bytes":null,"sql":["CREATE TABLE staging.sales.orders SHALLOW CLONE prod.sales.orders","ALTER TABLE staging.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = 'orders-id')"],"copy_files":null,"status":"not_run","duration_ms":null,"error":null}
+        "#);
+    }
+
+    #[test]
+    fn dropped_catalogs_say_when_they_expired() {
+        let mut expired = catalog("pr_42", vec![]);
+        expired.tags.insert(crate::ephemeral::EXPIRES_AT_TAG.to_string(), "2026-01-01T00:00:00+00:00".to_string());
+        let nodes = crate::ephemeral::gc(std::slice::from_ref(&expired), Utc::now());
+        let entry = describe(nodes[0].operation.as_ref().unwrap(), "", 0);
+        assert_eq!(entry.reason.as_deref(), Some("expired at 2026-01-01T00:00:00+00:00"));
+    }
+}
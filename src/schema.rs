@@ -1,22 +1,331 @@
+use anyhow::Context;
+use regex::Regex;
 use serde::Deserialize;
 use serde_yaml::from_str;
+use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
 
+/// A source catalog and the pinned catalogs kept in sync with it.
 #[derive(Debug, Deserialize)]
 pub struct SyncEntry {
     pub catalog: String,
     pub pinned_catalogs: Vec<String>,
+    #[serde(default)]
+    pub permissions: Option<PermissionConfig>,
+    #[serde(default)]
+    pub metadata: Option<MetadataConfig>,
+    #[serde(default)]
+    pub volumes: Option<VolumeConfig>,
+    /// Sync SQL functions, rewriting references to the source catalog.
+    #[serde(default)]
+    pub functions: bool,
+    #[serde(default)]
+    pub materialized_views: DerivedTableStrategy,
+    #[serde(default)]
+    pub streaming_tables: DerivedTableStrategy,
+    /// Tables copied as a sample or filtered subset instead of a full clone.
+    /// The first rule whose pattern matches a table applies.
+    #[serde(default)]
+    pub subsets: Vec<SubsetConfig>,
+    /// Column masking rules. Tables with a matching column are copied with a
+    /// masked CTAS instead of a clone. The first matching rule applies.
+    #[serde(default)]
+    pub masking: Vec<MaskingConfig>,
+    /// Cron expression for `serve`, with five fields or six starting with seconds.
+    pub schedule: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct MaskingConfig {
+    /// Column tag, as `key` or `key=value`, e.g. `pii=email`.
+    pub tag: Option<String>,
+    /// Column name glob, matched case-insensitively, e.g. `*email*`.
+    pub columns: Option<String>,
+    pub transform: MaskTransform,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskTransform {
+    /// SHA-256 of the value, as a string.
+    Hash,
+    /// NULL of the column's type.
+    Null,
+    /// A SQL expression, which may refer to the column, e.g. `concat('user', id, '@example.com')`.
+    Replace(String),
+}
+
+impl std::fmt::Display for MaskTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaskTransform::Hash => write!(f, "hash"),
+            MaskTransform::Null => write!(f, "null"),
+            MaskTransform::Replace(expression) => write!(f, "replace({})", expression),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubsetConfig {
+    /// `schema.table` glob, e.g. `sales.fact_*`.
+    pub tables: String,
+    pub sample_percent: Option<f64>,
+    #[serde(rename = "where")]
+    pub predicate: Option<String>,
+}
+
+/// Compiles a glob where `*` matches any run of characters and `?` a single one.
+pub fn glob_to_regex(glob: &str) -> Regex {
+    let pattern = regex::escape(glob).replace(r"\*", ".*").replace(r"\?", ".");
+    Regex::new(&format!("^{}$", pattern)).unwrap()
+}
+
+/// Parses a cron expression. Five fields are the usual minute to day of week,
+/// where days are numbered 0-7 from Sunday as in crontab. Six or seven fields
+/// add seconds in front and a year at the end, and number days 1-7 from Sunday.
+pub fn parse_schedule(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    match fields[..] {
+        [minute, hour, day, month, day_of_week] => {
+            let day_of_week = name_days_of_week(day_of_week).map_err(cron::error::ErrorKind::Expression)?;
+            format!("0 {} {} {} {} {}", minute, hour, day, month, day_of_week).parse()
+        }
+        _ => expression.parse(),
+    }
+}
+
+/// Names of the crontab day of week numbers, where both 0 and 7 are Sunday.
+const DAYS_OF_WEEK: [&str; 8] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT", "SUN"];
+
+/// Replaces the day numbers of a crontab day of week field with day names, which
+/// mean the same to the cron crate. Its own numbers start at 1 for Sunday, so
+/// `1-5` would run from Sunday to Thursday.
+fn name_days_of_week(field: &str) -> Result<String, String> {
+    let name = |day: &str| match day.parse::<usize>() {
+        Ok(number) => DAYS_OF_WEEK
+            .get(number)
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("day of week {} is out of range 0-7", day)),
+        Err(_) => Ok(day.to_string()),
+    };
+    let parts = field.split(',').map(|part| {
+        let (days, step) = match part.split_once('/') {
+            Some((days, step)) => (days, Some(step)),
+            None => (part, None),
+        };
+        let named = match days.split_once('-') {
+            // A range ending on Sunday as 7 wraps around, which named ranges cannot.
+            Some((start, "7")) => match (start, step) {
+                ("0", _) => "SUN-SAT".to_string(),
+                ("7", _) => "SUN".to_string(),
+                (start, None) => format!("{}-SAT,SUN", name(start)?),
+                (_, Some(_)) => return Err(format!("write {} with day names, such as MON-FRI", field)),
+            },
+            Some((start, end)) => format!("{}-{}", name(start)?, name(end)?),
+            None => name(days)?,
+        };
+        Ok(match step {
+            Some(step) => format!("{}/{}", named, step),
+            None => named,
+        })
+    });
+    Ok(parts.collect::<Result<Vec<_>, String>>()?.join(","))
+}
+
+/// How to sync tables that cannot be cloned, such as materialized views and streaming tables.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DerivedTableStrategy {
+    /// Leave them out of the sync and report them as skipped.
+    #[default]
+    Skip,
+    /// Copy the current contents into a plain Delta table.
+    Snapshot,
+    /// Recreate them from their definition in the target.
+    Recreate,
+}
+
+/// What to do with a MANAGED volume that is missing in the target.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ManagedVolumeStrategy {
+    /// Leave managed volumes out of the sync.
+    #[default]
+    Skip,
+    /// Create an empty volume with the same name.
+    CreateEmpty,
+    /// Create the volume and copy its files from the source.
+    CopyFiles,
+}
+
+/// Which kind of server `host` points at.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MetastoreKind {
+    /// A Databricks workspace, over HTTPS.
+    #[default]
+    Databricks,
+    /// An open-source Unity Catalog server, over plain HTTP unless `host` has a scheme.
+    Oss,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VolumeConfig {
+    #[serde(default)]
+    pub managed: ManagedVolumeStrategy,
+    /// Per pinned catalog, storage location prefix in the source to prefix in the target.
+    /// External volumes whose location matches no prefix are created over the same location.
+    #[serde(default)]
+    pub location_mapping: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataConfig {
+    #[serde(default = "default_true")]
+    pub comments: bool,
+    #[serde(default = "default_true")]
+    pub tags: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PermissionConfig {
+    #[serde(default = "default_true")]
+    pub sync_grants: bool,
+    #[serde(default)]
+    pub sync_ownership: bool,
+    /// Per pinned catalog, source principal to target principal.
+    #[serde(default)]
+    pub principal_mapping: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EphemeralConfig {
+    /// Glob that ephemeral catalog names must match. Garbage collection only
+    /// considers catalogs matching it.
+    #[serde(default = "default_ephemeral_pattern")]
+    pub name_pattern: String,
+}
+
+impl Default for EphemeralConfig {
+    fn default() -> Self {
+        Self {
+            name_pattern: default_ephemeral_pattern(),
+        }
+    }
+}
+
+/// The YAML config file, see [`load_config`].
 #[derive(Debug, Deserialize)]
 pub struct SyncConfig {
     pub catalogs: Vec<SyncEntry>,
     pub host: String,
+    /// Access token. Optional for open-source Unity Catalog servers without authentication.
+    #[serde(default)]
     pub pat: String,
+    #[serde(default)]
+    pub metastore: MetastoreKind,
+    /// SQL warehouse that runs statements when changes are applied.
+    pub warehouse_id: Option<String>,
+    #[serde(default)]
+    pub ephemeral: EphemeralConfig,
 }
 
-pub fn load_config(path: &str) -> Result<SyncConfig, Box<dyn std::error::Error>> {
-    let config_string = fs::read_to_string(path)?;
-    let config: SyncConfig = from_str(&config_string)?;
+fn default_true() -> bool {
+    true
+}
+
+fn default_ephemeral_pattern() -> String {
+    "pr_*".to_string()
+}
+
+impl SyncConfig {
+    /// Checks that can be made without looking at the metastore. Catalog types
+    /// are only known after crawling and are checked separately.
+    pub fn validate(&self) -> Result<(), String> {
+        for entry in &self.catalogs {
+            for pinned_catalog in &entry.pinned_catalogs {
+                if pinned_catalog == &entry.catalog {
+                    return Err(format!("catalog {} is pinned to itself", entry.catalog));
+                }
+                if pinned_catalog == "system" {
+                    return Err(format!(
+                        "catalog {} cannot be pinned to the read-only system catalog",
+                        entry.catalog
+                    ));
+                }
+            }
+            for subset in &entry.subsets {
+                if subset.sample_percent.is_none() && subset.predicate.is_none() {
+                    return Err(format!("subset {} needs sample_percent or where", subset.tables));
+                }
+                if subset.sample_percent.is_some_and(|p| p <= 0.0 || p > 100.0) {
+                    return Err(format!("subset {} needs a sample_percent in (0, 100]", subset.tables));
+                }
+            }
+            for rule in &entry.masking {
+                if rule.tag.is_some() == rule.columns.is_some() {
+                    return Err(format!(
+                        "masking rules of {} need exactly one of tag or columns",
+                        entry.catalog
+                    ));
+                }
+            }
+            if let Some(schedule) = &entry.schedule {
+                parse_schedule(schedule)
+                    .map_err(|e| format!("schedule {} of {} is invalid: {}", schedule, entry.catalog, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads and validates a YAML config file.
+pub fn load_config(path: impl AsRef<Path>) -> anyhow::Result<SyncConfig> {
+    let path = path.as_ref();
+    let config_string = fs::read_to_string(path).with_context(|| format!("reading config {}", path.display()))?;
+    let config: SyncConfig = from_str(&config_string).with_context(|| format!("parsing config {}", path.display()))?;
+    config.validate().map_err(anyhow::Error::msg)?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, Utc};
+    use std::collections::BTreeSet;
+
+    /// Days of the week an expression fires on over the next two weeks, as
+    /// days from Sunday.
+    fn days(expression: &str) -> BTreeSet<u32> {
+        parse_schedule(expression)
+            .unwrap()
+            .upcoming(Utc)
+            .take(14)
+            .map(|time| time.weekday().num_days_from_sunday())
+            .collect()
+    }
+
+    #[test]
+    fn five_fields_number_days_from_sunday_as_zero() {
+        assert_eq!(days("0 6 * * 1-5"), BTreeSet::from([1, 2, 3, 4, 5]));
+        assert_eq!(days("0 6 * * MON-FRI"), days("0 6 * * 1-5"));
+        assert_eq!(days("0 6 * * 0"), BTreeSet::from([0]));
+        assert_eq!(days("0 6 * * 7"), BTreeSet::from([0]));
+        assert_eq!(days("0 6 * * 5-7"), BTreeSet::from([0, 5, 6]));
+        assert_eq!(days("0 6 * * 1,3"), BTreeSet::from([1, 3]));
+        assert_eq!(days("0 6 * * 0-7/2"), BTreeSet::from([0, 2, 4, 6]));
+        assert_eq!(days("0 6 * * *").len(), 7);
+    }
+
+    #[test]
+    fn six_fields_keep_the_cron_crate_numbering() {
+        assert_eq!(days("0 0 6 * * 2-6"), days("0 6 * * 1-5"));
+    }
+
+    #[test]
+    fn unclear_days_of_week_are_rejected() {
+        assert!(parse_schedule("0 6 * * 8").is_err());
+        assert!(parse_schedule("0 6 * * 1-7/2").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::apply::Status;
+use crate::schema::{parse_schedule, SyncConfig, SyncEntry};
+use crate::crawler::Crawler;
+use crate::plan::Plan;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// State of one scheduled entry, as written to the status file.
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryStatus {
+    pub catalog: String,
+    pub pinned_catalogs: Vec<String>,
+    pub schedule: String,
+    pub running: bool,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<LastRun>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub succeeded: bool,
+    /// Number of operations per status, over all pinned catalogs.
+    pub operations: BTreeMap<Status, usize>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StatusFile<'a> {
+    updated_at: DateTime<Utc>,
+    entries: &'a [EntryStatus],
+}
+
+/// Scheduled entries and where their status is written after every change.
+struct Scheduler<'a> {
+    crawler: &'a Crawler,
+    warehouse_id: Option<&'a str>,
+    status: Mutex<Vec<EntryStatus>>,
+    status_path: &'a Path,
+}
+
+impl Scheduler<'_> {
+    fn update(&self, index: usize, change: impl FnOnce(&mut EntryStatus)) {
+        let mut status = self.status.lock().unwrap();
+        change(&mut status[index]);
+        let file = StatusFile {
+            updated_at: Utc::now(),
+            entries: &status,
+        };
+        // Write next to the file and rename, so readers never see a partial file.
+        let temporary = self.status_path.with_extension("json.tmp");
+        let written = serde_json::to_vec_pretty(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(&temporary, json)?))
+            .and_then(|_| Ok(std::fs::rename(&temporary, self.status_path)?));
+        if let Err(e) = written {
+            warn!("Could not write status file {}: {}", self.status_path.display(), e);
+        }
+    }
+
+    /// Runs one entry at each time of its schedule until shutdown. A run is awaited
+    /// before the next time is computed, so runs of an entry never overlap and times
+    /// that pass during a run are skipped.
+    async fn run_on_schedule(&self, index: usize, entry: &SyncEntry, mut shutdown: watch::Receiver<bool>) {
+        let schedule = parse_schedule(entry.schedule.as_deref().unwrap()).unwrap();
+        while !*shutdown.borrow() {
+            let Some(next) = schedule.upcoming(Utc).next() else {
+                info!("Schedule of {} has no more times", entry.catalog);
+                break;
+            };
+            self.update(index, |status| status.next_run = Some(next));
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.changed() => break,
+            }
+
+            info!("Syncing {}", entry.catalog);
+            let started_at = Utc::now();
+            self.update(index, |status| {
+                status.running = true;
+                status.next_run = None;
+            });
+            let result = async {
+                let mut plan = Plan::new(self.crawler, std::slice::from_ref(entry), &[]).await?;
+                match self.warehouse_id {
+                    Some(warehouse_id) => plan.apply(self.crawler.backend.as_ref(), warehouse_id).await,
+                    None => {
+                        for run in &plan.runs {
+                            info!("{} -> {}: {}", run.source, run.target, run.summary());
+                        }
+                    }
+                }
+                anyhow::Ok(plan)
+            }
+            .await;
+            let mut last_run = LastRun {
+                started_at,
+                finished_at: Utc::now(),
+                succeeded: false,
+                operations: BTreeMap::new(),
+                error: None,
+            };
+            match result {
+                Ok(plan) => {
+                    for run in &plan.runs {
+                        for (status, count) in run.counts() {
+                            *last_run.operations.entry(status).or_default() += count;
+                        }
+                    }
+                    last_run.error = plan
+                        .runs
+                        .iter()
+                        .flat_map(|run| &run.outcomes)
+                        .find_map(|outcome| outcome.error.clone());
+                    last_run.succeeded = last_run.error.is_none();
+                }
+                Err(e) => {
+                    error!("Syncing {} failed: {:#}", entry.catalog, e);
+                    last_run.error = Some(format!("{:#}", e));
+                }
+            }
+            self.update(index, |status| {
+                status.running = false;
+                status.last_run = Some(last_run);
+            });
+        }
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+#[cfg(unix)]
+pub async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        result = tokio::signal::ctrl_c() => result?,
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> Result<()> {
+    Ok(tokio::signal::ctrl_c().await?)
+}
+
+/// Syncs every entry with a `schedule` on its schedule until SIGTERM or Ctrl-C,
+/// or until no schedule has a next run. Running syncs are finished before
+/// returning. Without a warehouse, the plans
+/// are only logged.
+pub async fn serve(crawler: &Crawler, config: &SyncConfig, warehouse_id: Option<&str>, status_path: &Path) -> Result<()> {
+    let scheduled: Vec<&SyncEntry> = config.catalogs.iter().filter(|e| e.schedule.is_some()).collect();
+    if scheduled.is_empty() {
+        bail!("no catalog in the config has a schedule");
+    }
+    if let Some(parent) = status_path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+    }
+    let scheduler = Scheduler {
+        crawler,
+        warehouse_id,
+        status: Mutex::new(
+            scheduled
+                .iter()
+                .map(|entry| EntryStatus {
+                    catalog: entry.catalog.clone(),
+                    pinned_catalogs: entry.pinned_catalogs.clone(),
+                    schedule: entry.schedule.clone().unwrap(),
+                    running: false,
+                    next_run: None,
+                    last_run: None,
+                })
+                .collect(),
+        ),
+        status_path,
+    };
+
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let runs = futures::future::join_all(
+        scheduled
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| scheduler.run_on_schedule(index, entry, shutdown.clone())),
+    );
+    info!("Scheduled {} catalogs, status in {}", scheduled.len(), status_path.display());
+    tokio::pin!(runs);
+    tokio::select! {
+        _ = &mut runs => {
+            info!("Every schedule has ended");
+            Ok(())
+        }
+        result = shutdown_signal() => {
+            info!("Shutting down once running syncs finish");
+            let _ = shutdown_sender.send(true);
+            runs.await;
+            result
+        }
+    }
+}
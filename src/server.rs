@@ -0,0 +1,531 @@
+use crate::apply::{self, Limits, Outcome, Status};
+use crate::report::{self, Run};
+use crate::schema::{SyncConfig, SyncEntry};
+use crate::crawler::Crawler;
+use crate::plan::Plan;
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Plans and runs kept in memory for the lifetime of the server.
+pub struct Server {
+    config: SyncConfig,
+    crawler: Crawler,
+    /// Snapshots that plans read catalogs from instead of crawling them.
+    snapshots: Vec<PathBuf>,
+    state: Mutex<ServerState>,
+}
+
+#[derive(Default)]
+struct ServerState {
+    next_id: u64,
+    plans: BTreeMap<String, Arc<StoredPlan>>,
+    runs: BTreeMap<String, StoredRun>,
+}
+
+struct StoredPlan {
+    id: String,
+    catalog: String,
+    created_at: DateTime<Utc>,
+    runs: Vec<Run>,
+    /// Run that applies the plan. A plan can only be applied once.
+    run_id: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RunStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+struct StoredRun {
+    plan_id: String,
+    status: RunStatus,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    /// Outcomes known so far, per pinned catalog of the plan.
+    outcomes: Vec<Vec<Outcome>>,
+    logs: Vec<String>,
+}
+
+/// Error response with a JSON body of the form `{"error": "..."}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn not_found(what: &str, id: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("{} {} not found", what, id))
+}
+
+#[derive(Serialize)]
+struct EntryResponse<'a> {
+    catalog: &'a str,
+    pinned_catalogs: &'a [String],
+    schedule: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct PlanResponse<'a> {
+    id: &'a str,
+    catalog: &'a str,
+    created_at: DateTime<Utc>,
+    run_id: Option<String>,
+    plan: report::Document<'a>,
+}
+
+#[derive(Serialize)]
+struct RunResponse<'a> {
+    id: &'a str,
+    plan_id: &'a str,
+    status: RunStatus,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    /// Operations of the plan, with the status of those that ran so far.
+    result: report::Document<'a>,
+}
+
+impl Server {
+    pub fn new(config: SyncConfig, crawler: Crawler, snapshots: Vec<PathBuf>) -> Self {
+        Self {
+            config,
+            crawler,
+            snapshots,
+            state: Mutex::new(ServerState::default()),
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/entries", get(list_entries))
+            .route("/entries/{catalog}/plans", post(create_plan))
+            .route("/plans/{id}", get(get_plan))
+            .route("/plans/{id}/apply", post(apply_plan))
+            .route("/runs/{id}", get(get_run))
+            .route("/runs/{id}/logs", get(get_run_logs))
+            .with_state(self)
+    }
+
+    fn next_id(&self, prefix: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        format!("{}-{}", prefix, state.next_id)
+    }
+
+    fn entry(&self, catalog: &str) -> Option<&SyncEntry> {
+        self.config.catalogs.iter().find(|entry| entry.catalog == catalog)
+    }
+
+    fn plan(&self, id: &str) -> Result<Arc<StoredPlan>, ApiError> {
+        let state = self.state.lock().unwrap();
+        state.plans.get(id).cloned().ok_or_else(|| not_found("plan", id))
+    }
+
+    fn log(&self, run_id: &str, message: String) {
+        info!("{}: {}", run_id, message);
+        let mut state = self.state.lock().unwrap();
+        if let Some(run) = state.runs.get_mut(run_id) {
+            run.logs.push(format!("{} {}", Utc::now().to_rfc3339(), message));
+        }
+    }
+
+    /// Applies every pinned catalog of the plan in turn, recording outcomes and logs
+    /// as they happen.
+    async fn run(&self, plan: &StoredPlan, run_id: &str, warehouse_id: &str) {
+        let mut failed = false;
+        for (index, run) in plan.runs.iter().enumerate() {
+            self.log(run_id, format!("Applying {} -> {}", run.source, run.target));
+            let outcomes = apply::apply(self.crawler.backend.as_ref(), warehouse_id, &run.queries, &Limits::default(), |query, outcome| {
+                let mut state = self.state.lock().unwrap();
+                let Some(stored) = state.runs.get_mut(run_id) else {
+                    return;
+                };
+                stored.outcomes[index].push(outcome.clone());
+                if let Some(entry) = &run.queries[query].entry {
+                    let mut message = format!("{} {} {}", outcome.status, entry.kind, entry.object);
+                    if let Some(duration) = outcome.duration {
+                        message.push_str(&format!(" in {} ms", duration.as_millis()));
+                    }
+                    if let Some(error) = &outcome.error {
+                        message.push_str(&format!(": {}", error));
+                    }
+                    stored.logs.push(format!("{} {}", Utc::now().to_rfc3339(), message));
+                }
+            })
+            .await;
+            failed |= outcomes.iter().any(|outcome| outcome.status == Status::Failed);
+        }
+        let (status, message) = match failed {
+            true => (RunStatus::Failed, "Finished with failures"),
+            false => (RunStatus::Succeeded, "Finished"),
+        };
+        self.log(run_id, message.to_string());
+        let mut state = self.state.lock().unwrap();
+        if let Some(stored) = state.runs.get_mut(run_id) {
+            stored.status = status;
+            stored.finished_at = Some(Utc::now());
+        }
+    }
+}
+
+async fn list_entries(State(server): State<Arc<Server>>) -> Response {
+    let entries: Vec<EntryResponse> = server
+        .config
+        .catalogs
+        .iter()
+        .map(|entry| EntryResponse {
+            catalog: &entry.catalog,
+            pinned_catalogs: &entry.pinned_catalogs,
+            schedule: entry.schedule.as_deref(),
+        })
+        .collect();
+    Json(entries).into_response()
+}
+
+fn plan_response(plan: &StoredPlan) -> Response {
+    Json(PlanResponse {
+        id: &plan.id,
+        catalog: &plan.catalog,
+        created_at: plan.created_at,
+        run_id: plan.run_id.lock().unwrap().clone(),
+        plan: report::json_document(&plan.runs),
+    })
+    .into_response()
+}
+
+/// Crawls the catalogs of an entry, or reads them from the snapshots, and plans
+/// every pinned catalog of it.
+async fn create_plan(State(server): State<Arc<Server>>, Path(catalog): Path<String>) -> Result<Response, ApiError> {
+    let entry = server.entry(&catalog).ok_or_else(|| not_found("entry", &catalog))?;
+    let plan = Plan::new(&server.crawler, std::slice::from_ref(entry), &server.snapshots)
+        .await
+        .map_err(|e| {
+            error!("Planning {} failed: {:#}", catalog, e);
+            ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+        })?;
+    let plan = Arc::new(StoredPlan {
+        id: server.next_id("plan"),
+        catalog,
+        created_at: Utc::now(),
+        runs: plan.runs,
+        run_id: Mutex::new(None),
+    });
+    server.state.lock().unwrap().plans.insert(plan.id.clone(), plan.clone());
+    let mut response = plan_response(&plan);
+    *response.status_mut() = StatusCode::CREATED;
+    Ok(response)
+}
+
+async fn get_plan(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<Response, ApiError> {
+    let plan = server.plan(&id)?;
+    Ok(plan_response(&plan))
+}
+
+/// Starts applying a plan in the background and returns its run right away.
+async fn apply_plan(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<Response, ApiError> {
+    let plan = server.plan(&id)?;
+    let Some(warehouse_id) = server.config.warehouse_id.clone() else {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            "warehouse_id must be configured to run statements".to_string(),
+        ));
+    };
+    let run_id = {
+        let mut applied = plan.run_id.lock().unwrap();
+        if let Some(run_id) = applied.as_ref() {
+            return Err(ApiError(
+                StatusCode::CONFLICT,
+                format!("plan {} was already applied by run {}", id, run_id),
+            ));
+        }
+        let run_id = server.next_id("run");
+        *applied = Some(run_id.clone());
+        run_id
+    };
+    server.state.lock().unwrap().runs.insert(
+        run_id.clone(),
+        StoredRun {
+            plan_id: plan.id.clone(),
+            status: RunStatus::Running,
+            started_at: Utc::now(),
+            finished_at: None,
+            outcomes: vec![vec![]; plan.runs.len()],
+            logs: vec![],
+        },
+    );
+    let mut response = run_response(&server, &run_id)?;
+    *response.status_mut() = StatusCode::ACCEPTED;
+    tokio::spawn(async move { server.run(&plan, &run_id, &warehouse_id).await });
+    Ok(response)
+}
+
+fn run_response(server: &Server, id: &str) -> Result<Response, ApiError> {
+    let state = server.state.lock().unwrap();
+    let run = state.runs.get(id).ok_or_else(|| not_found("run", id))?;
+    let plan = state.plans.get(&run.plan_id).ok_or_else(|| not_found("plan", &run.plan_id))?;
+    let runs: Vec<Run> = plan
+        .runs
+        .iter()
+        .zip(&run.outcomes)
+        .map(|(planned, outcomes)| Run {
+            source: planned.source.clone(),
+            target: planned.target.clone(),
+            queries: planned.queries.clone(),
+            outcomes: outcomes.clone(),
+        })
+        .collect();
+    Ok(Json(RunResponse {
+        id,
+        plan_id: &run.plan_id,
+        status: run.status,
+        started_at: run.started_at,
+        finished_at: run.finished_at,
+        result: report::json_document(&runs),
+    })
+    .into_response())
+}
+
+async fn get_run(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<Response, ApiError> {
+    run_response(&server, &id)
+}
+
+/// Log lines of a run as plain text, oldest first.
+async fn get_run_logs(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<Response, ApiError> {
+    let state = server.state.lock().unwrap();
+    let run = state.runs.get(&id).ok_or_else(|| not_found("run", &id))?;
+    let mut logs = run.logs.join("\n");
+    if !logs.is_empty() {
+        logs.push('\n');
+    }
+    Ok(logs.into_response())
+}
+
+/// Serves the API on `address` until SIGTERM or Ctrl-C.
+pub async fn run_server(server: Server, address: SocketAddr) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, Arc::new(server).router())
+        .with_graceful_shutdown(async {
+            if let Err(e) = crate::serve::shutdown_signal().await {
+                error!("Could not listen for shutdown signals: {}", e);
+                std::future::pending::<()>().await;
+            }
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore;
+    use crate::querygen::tests::{catalog, schema, table};
+    use crate::unitycatalog::UnityCatalog;
+    use crate::crawler::CrawlMode;
+    use serde_json::Value;
+
+    const CONFIG: &str = "
+host: 127.0.0.1:1
+pat: token
+catalogs:
+  - catalog: prod
+    pinned_catalogs: [staging]
+    schedule: '0 2 * * *'
+  - catalog: finance
+    pinned_catalogs: [finance_dev]
+";
+
+    /// Server on a random port that plans from a snapshot, so nothing reaches a workspace.
+    /// `prod` is in sync with `staging`, `finance_dev` is empty.
+    struct TestServer {
+        url: String,
+        client: reqwest::Client,
+        _dir: tempfile::TempDir,
+    }
+
+    impl TestServer {
+        async fn start(warehouse_id: Option<&str>) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let mut unity_catalog = UnityCatalog::new();
+            let orders = |catalog_name| schema(catalog_name, "sales", vec![table(catalog_name, "sales", "orders", "MANAGED", 5)]);
+            for catalog in [
+                catalog("prod", vec![orders("prod")]),
+                catalog("staging", vec![orders("staging")]),
+                catalog(
+                    "finance",
+                    vec![schema("finance", "ledger", vec![table("finance", "ledger", "entries", "MANAGED", 5)])],
+                ),
+                catalog("finance_dev", vec![]),
+            ] {
+                unity_catalog.catalogs.insert(catalog.name.clone(), catalog);
+            }
+            let snapshot = dir.path().join("snapshot.json");
+            unity_catalog.save(&snapshot).unwrap();
+
+            let mut config: SyncConfig = serde_yaml::from_str(CONFIG).unwrap();
+            config.warehouse_id = warehouse_id.map(str::to_string);
+            let crawler = Crawler {
+                backend: metastore::from_config(&config),
+                concurrency: 1,
+                mode: CrawlMode::Full,
+                cache_path: dir.path().join("cache.json"),
+                no_cache: true,
+            };
+            let server = Arc::new(Server::new(config, crawler, vec![snapshot]));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, server.router()).await.unwrap() });
+            Self {
+                url,
+                client: reqwest::Client::new(),
+                _dir: dir,
+            }
+        }
+
+        async fn get(&self, path: &str) -> (StatusCode, Value) {
+            let response = self.client.get(format!("{}{}", self.url, path)).send().await.unwrap();
+            (response.status(), response.json().await.unwrap())
+        }
+
+        async fn post(&self, path: &str) -> (StatusCode, Value) {
+            let response = self.client.post(format!("{}{}", self.url, path)).send().await.unwrap();
+            (response.status(), response.json().await.unwrap())
+        }
+
+        async fn logs(&self, run_id: &str) -> String {
+            let url = format!("{}/runs/{}/logs", self.url, run_id);
+            self.client.get(url).send().await.unwrap().text().await.unwrap()
+        }
+
+        /// Polls a run until it is no longer running.
+        async fn finished_run(&self, run_id: &str) -> Value {
+            for _ in 0..100 {
+                let (_, run) = self.get(&format!("/runs/{}", run_id)).await;
+                if run["status"] != "running" {
+                    return run;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            panic!("run {} did not finish", run_id);
+        }
+    }
+
+    fn operations(runs: &Value) -> Vec<(String, String, String)> {
+        runs["runs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|run| run["operations"].as_array().unwrap())
+            .map(|operation| {
+                let field = |name: &str| operation[name].as_str().unwrap().to_string();
+                (field("kind"), field("object"), field("status"))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lists_configured_entries() {
+        let server = TestServer::start(None).await;
+        let (status, entries) = server.get("/entries").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            entries,
+            json!([
+                {"catalog": "prod", "pinned_catalogs": ["staging"], "schedule": "0 2 * * *"},
+                {"catalog": "finance", "pinned_catalogs": ["finance_dev"], "schedule": null},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn plans_an_entry_and_serves_the_plan_by_id() {
+        let server = TestServer::start(None).await;
+        let (status, plan) = server.post("/entries/finance/plans").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(plan["catalog"], "finance");
+        assert_eq!(plan["run_id"], Value::Null);
+        assert_eq!(plan["plan"]["schema_version"], report::SCHEMA_VERSION);
+        assert_eq!(
+            operations(&plan["plan"]),
+            [
+                ("create schema".to_string(), "finance_dev.ledger".to_string(), "planned".to_string()),
+                ("clone".to_string(), "finance_dev.ledger.entries".to_string(), "planned".to_string()),
+            ]
+        );
+        assert_eq!(
+            plan["plan"]["runs"][0]["operations"][1]["sql"][0],
+            "CREATE TABLE finance_dev.ledger.entries SHALLOW CLONE finance.ledger.entries"
+        );
+
+        let (status, fetched) = server.get(&format!("/plans/{}", plan["id"].as_str().unwrap())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fetched, plan);
+    }
+
+    #[tokio::test]
+    async fn unknown_entries_plans_and_runs_are_not_found() {
+        let server = TestServer::start(None).await;
+        let (status, body) = server.post("/entries/missing/plans").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({"error": "entry missing not found"}));
+        assert_eq!(server.get("/plans/plan-42").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(server.post("/plans/plan-42/apply").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(server.get("/runs/run-42").await.0, StatusCode::NOT_FOUND);
+        let response = server.client.get(format!("{}/runs/run-42/logs", server.url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn applies_a_plan_once_and_reports_its_run() {
+        let server = TestServer::start(Some("warehouse")).await;
+        let (_, plan) = server.post("/entries/prod/plans").await;
+        let plan_id = plan["id"].as_str().unwrap();
+        assert!(operations(&plan["plan"]).is_empty());
+
+        let (status, run) = server.post(&format!("/plans/{}/apply", plan_id)).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(run["plan_id"], plan_id);
+        let run_id = run["id"].as_str().unwrap();
+
+        let run = server.finished_run(run_id).await;
+        assert_eq!(run["status"], "succeeded");
+        assert_ne!(run["finished_at"], Value::Null);
+        let logs = server.logs(run_id).await;
+        assert!(logs.contains("Applying prod -> staging"), "{}", logs);
+        assert!(logs.trim_end().ends_with("Finished"), "{}", logs);
+
+        let (_, plan) = server.get(&format!("/plans/{}", plan_id)).await;
+        assert_eq!(plan["run_id"], run_id);
+        let (status, body) = server.post(&format!("/plans/{}/apply", plan_id)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body["error"],
+            format!("plan {} was already applied by run {}", plan_id, run_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn applying_needs_a_warehouse() {
+        let server = TestServer::start(None).await;
+        let (_, plan) = server.post("/entries/finance/plans").await;
+        let (status, body) = server.post(&format!("/plans/{}/apply", plan["id"].as_str().unwrap())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "warehouse_id must be configured to run statements");
+    }
+}
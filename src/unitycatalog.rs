@@ -1,9 +1,37 @@
+use crate::schema::{DerivedTableStrategy, ManagedVolumeStrategy, MaskTransform};
 use chrono::Utc;
 use chrono::{DateTime, Duration};
-use std::collections::{HashMap, HashSet};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 
-#[derive(Debug)]
+/// Direct privilege grants on a securable, keyed by principal.
+pub type Grants = BTreeMap<String, BTreeSet<String>>;
+
+/// Table property recording the `table_id` of the source a target table was copied from.
+pub const SOURCE_TABLE_ID_PROPERTY: &str = "dbsync.source_table_id";
+
+/// Table property recording the source's `updated_at`, in milliseconds, when a
+/// subset copy was made. Such copies carry no clone metadata to compare against.
+pub const SOURCE_UPDATED_AT_PROPERTY: &str = "dbsync.source_updated_at";
+
+/// Table property recording the subset clause a copy was made with.
+pub const SUBSET_PROPERTY: &str = "dbsync.subset";
+
+/// Table property recording which columns a copy was masked with, and how.
+pub const MASKING_PROPERTY: &str = "dbsync.masking";
+
+/// Table property holding the size in bytes that `ANALYZE TABLE` computed, when it ran.
+pub const TOTAL_SIZE_PROPERTY: &str = "spark.sql.statistics.totalSize";
+
+/// Unity Catalog tags on a securable or column, tag key to tag value.
+pub type Tags = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Table {
     pub name: String,
     pub schema_name: String,
@@ -11,39 +39,417 @@ pub struct Table {
     pub table_type: String,
     pub data_source_format: Option<String>,
     pub updated_at: DateTime<Utc>,
+    #[serde(rename = "updated_by")]
     pub _updated_by: String,
-    pub _properties: HashMap<String, String>,
+    pub table_id: Option<String>,
+    pub properties: HashMap<String, String>,
+    pub owner: Option<String>,
+    pub grants: Grants,
+    pub comment: Option<String>,
+    pub tags: Tags,
+    pub view_definition: Option<String>,
+    pub columns: Vec<Column>,
+    /// Listed without columns and properties. These are fetched lazily, only
+    /// for the tables a diff reads them from.
+    #[serde(default)]
+    pub brief: bool,
+}
+
+/// How a table is synced, derived from its `table_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    /// Holds its own data and can be shallow cloned.
+    Table,
+    /// Defined by a query only, recreated from its definition.
+    View,
+    /// Maintained by a pipeline or refresh schedule, cannot be cloned.
+    MaterializedView,
+    StreamingTable,
+}
+
+impl Table {
+    pub fn kind(&self) -> TableKind {
+        match self.table_type.as_str() {
+            "VIEW" => TableKind::View,
+            "MATERIALIZED_VIEW" => TableKind::MaterializedView,
+            "STREAMING_TABLE" => TableKind::StreamingTable,
+            _ => TableKind::Table,
+        }
+    }
+
+    /// Column differences between this table and `target`, a copy made with
+    /// `method`, in source column order followed by columns that only exist in
+    /// the target.
+    pub fn column_changes<'a>(&'a self, target: &'a Table, method: CloneMethod) -> Vec<ColumnChange<'a>> {
+        let mut changes: Vec<ColumnChange<'a>> = self
+            .columns
+            .iter()
+            .filter_map(|column| match target.columns.iter().find(|c| c.name == column.name) {
+                None => Some(ColumnChange::Added(column)),
+                Some(other) if !column.same_definition(other, method) => Some(ColumnChange::Changed {
+                    source: column,
+                    target: other,
+                }),
+                Some(_) => None,
+            })
+            .collect();
+        changes.extend(
+            target
+                .columns
+                .iter()
+                .filter(|c| !self.columns.iter().any(|column| column.name == c.name))
+                .map(ColumnChange::Removed),
+        );
+        changes
+    }
+
+    /// Size in bytes, known only for tables with statistics.
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.properties.get(TOTAL_SIZE_PROPERTY)?.parse().ok()
+    }
+
+    /// `table_id` of the source this table was copied from, if dbsync made the copy.
+    pub fn source_table_id(&self) -> Option<&str> {
+        self.properties.get(SOURCE_TABLE_ID_PROPERTY).map(String::as_str)
+    }
+
+    /// Whether `target` was copied from a source table that has since been
+    /// dropped and recreated under the same name. Its files then belong to a
+    /// table that no longer exists.
+    fn source_recreated(&self, target: &Table) -> bool {
+        match (self.table_id.as_deref(), target.source_table_id()) {
+            (Some(table_id), Some(cloned_from)) => table_id != cloned_from,
+            _ => false,
+        }
+    }
+
+    /// Why `target` has to be replaced by a fresh clone of this table, if at all.
+    fn clone_reason<'a>(&'a self, target: &'a Table, method: CloneMethod) -> Option<CloneReason<'a>> {
+        if self.source_recreated(target) {
+            return Some(CloneReason::SourceRecreated {
+                cloned_from: target.source_table_id().unwrap(),
+            });
+        }
+        if target.kind() != TableKind::Table {
+            return Some(CloneReason::KindChanged);
+        }
+        // A subset or masked copy that should now be a full clone.
+        if target.properties.contains_key(SUBSET_PROPERTY) || target.properties.contains_key(MASKING_PROPERTY) {
+            return Some(CloneReason::CopySpecChanged);
+        }
+        let column_changes = self.column_changes(target, method);
+        if !column_changes.is_empty() {
+            return Some(CloneReason::SchemaChanged(column_changes));
+        }
+        if self.is_stale(target, method) {
+            return Some(CloneReason::Stale);
+        }
+        None
+    }
+
+    /// Whether the target copy lags far enough behind the source to be refreshed.
+    /// Copies made with CTAS are also refreshed once they are older than the
+    /// window, as sources such as foreign tables do not bump `updated_at` when
+    /// their data changes.
+    fn is_stale(&self, target: &Table, method: CloneMethod) -> bool {
+        // TODO: Make duration configurable
+        let max_staleness = Duration::days(1);
+        self.updated_at - target.updated_at > max_staleness
+            || (method == CloneMethod::Ctas && Utc::now() - target.updated_at > max_staleness)
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+    pub type_text: String,
+    pub nullable: bool,
+    pub position: i64,
+    pub comment: Option<String>,
+    pub tags: Tags,
+}
+
+impl Column {
+    /// Whether `other`, the column of a copy made with `method`, matches this one.
+    /// CTAS drops `NOT NULL` and writes types the way Spark spells them, so such
+    /// copies are only compared by column position.
+    fn same_definition(&self, other: &Column, method: CloneMethod) -> bool {
+        match method {
+            CloneMethod::Ctas => self.position == other.position,
+            CloneMethod::Shallow | CloneMethod::Deep => {
+                self.type_text == other.type_text && self.nullable == other.nullable && self.position == other.position
+            }
+        }
+    }
+}
+
+/// Why a table is cloned into the target.
+#[derive(Debug)]
+pub enum CloneReason<'a> {
+    /// Missing in the target.
+    New,
+    /// The target is older than the staleness window allows.
+    Stale,
+    /// The target is a view or another kind of object that a clone replaces.
+    KindChanged,
+    SchemaChanged(Vec<ColumnChange<'a>>),
+    /// The source was dropped and recreated since the target was cloned from it.
+    SourceRecreated { cloned_from: &'a str },
+    /// The target was copied with a different subset or masking, or as a full clone.
+    CopySpecChanged,
+}
+
+/// Difference between a source column and the column of the same name in the target.
 #[derive(Debug)]
+pub enum ColumnChange<'a> {
+    Added(&'a Column),
+    Removed(&'a Column),
+    Changed { source: &'a Column, target: &'a Column },
+}
+
+impl std::fmt::Display for ColumnChange<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |c: &Column| {
+            format!(
+                "{} {}{} at {}",
+                c.name,
+                c.type_text,
+                if c.nullable { "" } else { " NOT NULL" },
+                c.position
+            )
+        };
+        match self {
+            ColumnChange::Added(column) => write!(f, "+ {}", describe(column)),
+            ColumnChange::Removed(column) => write!(f, "- {}", describe(column)),
+            ColumnChange::Changed { source, target } => write!(f, "~ {} -> {}", describe(target), describe(source)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Schema {
+    #[serde(rename = "name")]
     pub _name: String,
+    #[serde(rename = "catalog_name")]
     pub _catalog_name: String,
-    pub tables: HashMap<String, Table>,
+    pub owner: Option<String>,
+    pub grants: Grants,
+    pub comment: Option<String>,
+    pub tags: Tags,
+    /// Absent in caches written before it was recorded.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub tables: BTreeMap<String, Table>,
+    pub volumes: BTreeMap<String, Volume>,
+    pub functions: BTreeMap<String, Function>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionParameter {
+    pub name: String,
+    pub type_text: String,
+    pub default: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    pub schema_name: String,
+    pub catalog_name: String,
+    pub parameters: Vec<FunctionParameter>,
+    /// Return type, or the columns of a table function.
+    pub returns: FunctionReturns,
+    /// `None` for SQL functions, otherwise the external language such as `PYTHON`.
+    pub language: Option<String>,
+    pub deterministic: bool,
+    pub comment: Option<String>,
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FunctionReturns {
+    Scalar(String),
+    Table(Vec<FunctionParameter>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Volume {
+    pub name: String,
+    pub schema_name: String,
+    pub catalog_name: String,
+    pub volume_type: String,
+    pub storage_location: Option<String>,
+    #[serde(rename = "updated_at")]
+    pub _updated_at: DateTime<Utc>,
+}
+
+impl Volume {
+    pub fn is_managed(&self) -> bool {
+        self.volume_type == "MANAGED"
+    }
+}
+
+/// Kind of catalog as reported by Unity Catalog. Only managed catalogs can be
+/// written to, the others are read-only mirrors of data living elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogType {
+    #[default]
+    Managed,
+    Foreign {
+        connection_name: Option<String>,
+    },
+    DeltaSharing {
+        provider_name: Option<String>,
+        share_name: Option<String>,
+    },
+    System,
+}
+
+impl CatalogType {
+    pub fn is_read_only(&self) -> bool {
+        *self != CatalogType::Managed
+    }
+
+    /// How tables of a catalog of this type are copied into a managed catalog.
+    pub fn clone_method(&self) -> CloneMethod {
+        match self {
+            CatalogType::Managed => CloneMethod::Shallow,
+            CatalogType::DeltaSharing { .. } => CloneMethod::Deep,
+            CatalogType::Foreign { .. } | CatalogType::System => CloneMethod::Ctas,
+        }
+    }
+}
+
+impl std::fmt::Display for CatalogType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CatalogType::Managed => write!(f, "MANAGED"),
+            CatalogType::Foreign { connection_name } => {
+                write!(f, "FOREIGN (connection {})", connection_name.as_deref().unwrap_or("unknown"))
+            }
+            CatalogType::DeltaSharing { provider_name, share_name } => write!(
+                f,
+                "DELTASHARING (provider {}, share {})",
+                provider_name.as_deref().unwrap_or("unknown"),
+                share_name.as_deref().unwrap_or("unknown")
+            ),
+            CatalogType::System => write!(f, "SYSTEM"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CloneMethod {
+    #[default]
+    Shallow,
+    Deep,
+    /// `CREATE TABLE ... AS SELECT`, for sources that cannot be cloned at all.
+    Ctas,
+}
+
+/// A catalog with its schemas, and its options when it is a pinned catalog.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Catalog {
     pub name: String,
-    pub schemas: HashMap<String, Schema>,
+    pub catalog_type: CatalogType,
+    #[serde(default)]
+    pub owner: Option<String>,
+    pub grants: Grants,
+    pub comment: Option<String>,
+    pub tags: Tags,
+    pub schemas: BTreeMap<String, Schema>,
+}
+
+impl Catalog {
+    /// Brief tables, of this catalog or of `target`, whose columns or properties
+    /// the diff against `target` may read. The others are decided on `updated_at`
+    /// alone: a source not updated since its target was cannot have changed
+    /// columns or have been recreated. Views listed from table summaries come
+    /// without their definition and always need details.
+    pub fn tables_needing_details<'a>(&'a self, target: Option<&'a Catalog>, options: &DiffOptions) -> Vec<&'a Table> {
+        // Column comments, column tags and masking rules look at every column.
+        let all_columns = options.metadata.is_some() || !options.masking.is_empty();
+        let mut needed = vec![];
+        for schema in self.schemas.values() {
+            let target_schema = target.and_then(|t| t.schemas.get(&schema._name));
+            for table in schema.tables.values() {
+                let target_table = target_schema.and_then(|s| s.tables.get(&table.name));
+                let needs_details = all_columns
+                    || (table.kind() == TableKind::View && table.view_definition.is_none())
+                    || target_table.is_some_and(|target_table| match table.kind() {
+                        TableKind::View => false,
+                        TableKind::Table => {
+                            options.subset_for(table).is_some() || table.updated_at > target_table.updated_at
+                        }
+                        TableKind::MaterializedView | TableKind::StreamingTable => true,
+                    });
+                if needs_details {
+                    needed.push(table);
+                    needed.extend(target_table);
+                }
+            }
+        }
+        needed.retain(|table| table.brief);
+        needed
+    }
+}
+
+/// Grants fetched for a catalog, schema or table, located by its name parts.
+pub struct Permissions {
+    pub catalog_name: String,
+    pub schema_name: Option<String>,
+    pub table_name: Option<String>,
+    pub grants: Grants,
+}
+
+/// Tags fetched for a catalog, schema, table or column, located by its name parts.
+pub struct TagAssignments {
+    pub catalog_name: String,
+    pub schema_name: Option<String>,
+    pub table_name: Option<String>,
+    pub column_name: Option<String>,
+    pub tags: Tags,
 }
 
 pub enum UnityCatalogElement {
     Catalog(Catalog),
     Schema(Schema),
     Table(Table),
+    /// Columns and properties of a table that was listed brief.
+    TableDetails(Table),
+    Volume(Volume),
+    Function(Function),
+    Permissions(Permissions),
+    Tags(TagAssignments),
 }
+
+/// Crawled metastore tree. Can be saved as a JSON snapshot and loaded in place of a crawl.
+#[derive(Default, Serialize, Deserialize)]
 pub struct UnityCatalog {
-    pub catalogs: HashMap<String, Catalog>,
+    pub catalogs: BTreeMap<String, Catalog>,
 }
 
 impl UnityCatalog {
     pub fn new() -> Self {
         Self {
-            catalogs: HashMap::new(),
+            catalogs: BTreeMap::new(),
         }
     }
 
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(file)?)
+    }
+
     pub fn insert_assume_ordered(&mut self, element: UnityCatalogElement) {
         match element {
             UnityCatalogElement::Catalog(catalog) => {
@@ -66,40 +472,542 @@ impl UnityCatalog {
                     .tables
                     .insert(table.name.clone(), table);
             }
+            UnityCatalogElement::TableDetails(details) => {
+                let table = self
+                    .catalogs
+                    .get_mut(&details.catalog_name)
+                    .unwrap()
+                    .schemas
+                    .get_mut(&details.schema_name)
+                    .unwrap()
+                    .tables
+                    .get_mut(&details.name)
+                    .unwrap();
+                table.columns = details.columns;
+                table.properties = details.properties;
+                table.brief = false;
+            }
+            UnityCatalogElement::Volume(volume) => {
+                self.catalogs
+                    .get_mut(&volume.catalog_name)
+                    .unwrap()
+                    .schemas
+                    .get_mut(&volume.schema_name)
+                    .unwrap()
+                    .volumes
+                    .insert(volume.name.clone(), volume);
+            }
+            UnityCatalogElement::Function(function) => {
+                self.catalogs
+                    .get_mut(&function.catalog_name)
+                    .unwrap()
+                    .schemas
+                    .get_mut(&function.schema_name)
+                    .unwrap()
+                    .functions
+                    .insert(function.name.clone(), function);
+            }
+            UnityCatalogElement::Permissions(permissions) => {
+                let catalog = self.catalogs.get_mut(&permissions.catalog_name).unwrap();
+                let grants = match (&permissions.schema_name, &permissions.table_name) {
+                    (Some(schema), Some(table)) => {
+                        &mut catalog
+                            .schemas
+                            .get_mut(schema)
+                            .unwrap()
+                            .tables
+                            .get_mut(table)
+                            .unwrap()
+                            .grants
+                    }
+                    (Some(schema), None) => &mut catalog.schemas.get_mut(schema).unwrap().grants,
+                    _ => &mut catalog.grants,
+                };
+                *grants = permissions.grants;
+            }
+            UnityCatalogElement::Tags(assignments) => {
+                let catalog = self.catalogs.get_mut(&assignments.catalog_name).unwrap();
+                let tags = match (&assignments.schema_name, &assignments.table_name) {
+                    (Some(schema), Some(table)) => {
+                        let table = catalog.schemas.get_mut(schema).unwrap().tables.get_mut(table).unwrap();
+                        match &assignments.column_name {
+                            Some(column) => {
+                                &mut table.columns.iter_mut().find(|c| &c.name == column).unwrap().tags
+                            }
+                            None => &mut table.tags,
+                        }
+                    }
+                    (Some(schema), None) => &mut catalog.schemas.get_mut(schema).unwrap().tags,
+                    _ => &mut catalog.tags,
+                };
+                *tags = assignments.tags;
+            }
+        }
+    }
+}
+
+/// Options controlling how a source catalog is diffed against a pinned catalog.
+#[derive(Debug, Default)]
+pub struct DiffOptions {
+    pub permissions: Option<PermissionOptions>,
+    pub metadata: Option<MetadataOptions>,
+    pub volumes: Option<VolumeOptions>,
+    pub functions: bool,
+    pub materialized_views: DerivedTableStrategy,
+    pub streaming_tables: DerivedTableStrategy,
+    /// Derived from the source catalog's type.
+    pub clone_method: CloneMethod,
+    /// `schema.table` patterns of tables copied as a subset, first match wins.
+    pub subsets: Vec<(Regex, Subset)>,
+    /// Column masking rules, first match wins.
+    pub masking: Vec<MaskRule>,
+}
+
+impl DiffOptions {
+    fn subset_for(&self, table: &Table) -> Option<&Subset> {
+        let name = format!("{}.{}", table.schema_name, table.name);
+        self.subsets
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(&name))
+            .map(|(_, subset)| subset)
+    }
+
+    fn masked_columns<'a>(&self, table: &'a Table) -> Vec<MaskedColumn<'a>> {
+        table
+            .columns
+            .iter()
+            .filter_map(|column| {
+                self.masking
+                    .iter()
+                    .find(|rule| rule.matcher.matches(column))
+                    .map(|rule| MaskedColumn {
+                        column,
+                        transform: rule.transform.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// How `table` is copied when it is not cloned in full, or `None` for a clone.
+    fn copy_spec_for<'a>(&self, table: &'a Table) -> Option<CopySpec<'a>> {
+        let spec = CopySpec {
+            subset: self.subset_for(table).cloned(),
+            masked: self.masked_columns(table),
+        };
+        match spec.subset.is_none() && spec.masked.is_empty() {
+            true => None,
+            false => Some(spec),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ColumnMatcher {
+    Tag { key: String, value: Option<String> },
+    /// Lowercase column name pattern.
+    Name(Regex),
+}
+
+impl ColumnMatcher {
+    fn matches(&self, column: &Column) -> bool {
+        match self {
+            ColumnMatcher::Tag { key, value } => match (column.tags.get(key), value) {
+                (Some(tag_value), Some(value)) => tag_value == value,
+                (Some(_), None) => true,
+                (None, _) => false,
+            },
+            ColumnMatcher::Name(pattern) => pattern.is_match(&column.name.to_lowercase()),
         }
     }
 }
 
 #[derive(Debug)]
+pub struct MaskRule {
+    pub matcher: ColumnMatcher,
+    pub transform: MaskTransform,
+}
+
+#[derive(Debug)]
+pub struct MaskedColumn<'a> {
+    pub column: &'a Column,
+    pub transform: MaskTransform,
+}
+
+/// A copy made with CTAS instead of a clone: a subset of the rows, masked
+/// columns, or both.
+#[derive(Debug, Default)]
+pub struct CopySpec<'a> {
+    pub subset: Option<Subset>,
+    pub masked: Vec<MaskedColumn<'a>>,
+}
+
+impl CopySpec<'_> {
+    /// Table properties describing this spec, compared against the target to
+    /// tell whether it was copied the same way.
+    pub fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut properties = vec![];
+        if let Some(subset) = &self.subset {
+            properties.push((SUBSET_PROPERTY, subset.clause()));
+        }
+        if !self.masked.is_empty() {
+            properties.push((MASKING_PROPERTY, self.masking_summary()));
+        }
+        properties
+    }
+
+    /// Masked columns and their transforms, e.g. `email: hash, phone: null`.
+    pub fn masking_summary(&self) -> String {
+        self.masked
+            .iter()
+            .map(|m| format!("{}: {}", m.column.name, m.transform))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn matches(&self, target: &Table) -> bool {
+        let expected = self.properties();
+        [SUBSET_PROPERTY, MASKING_PROPERTY].iter().all(|key| {
+            target.properties.get(*key) == expected.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+        })
+    }
+}
+
+/// Sample and filter applied when copying a table with CTAS instead of cloning it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subset {
+    pub sample_percent: Option<f64>,
+    pub predicate: Option<String>,
+}
+
+impl Subset {
+    /// Clause following `SELECT * FROM <source>`.
+    pub fn clause(&self) -> String {
+        let mut clause = vec![];
+        if let Some(percent) = self.sample_percent {
+            clause.push(format!("TABLESAMPLE ({} PERCENT)", percent));
+        }
+        if let Some(predicate) = &self.predicate {
+            clause.push(format!("WHERE {}", predicate));
+        }
+        clause.join(" ")
+    }
+}
+
+/// Rewrites three-part names that start with catalog `from` so they point at
+/// catalog `to`, e.g. `prod.util.mask_email` to `staging.util.mask_email`.
+/// Backtick-quoted catalog names are rewritten as well.
+pub fn rewrite_catalog_references(sql: &str, from: &str, to: &str) -> String {
+    let pattern = format!(r"(?i)(^|[^\w.`])(`?){}(`?)\.", regex::escape(from));
+    Regex::new(&pattern)
+        .unwrap()
+        .replace_all(sql, |caps: &regex::Captures| format!("{}{}{}{}.", &caps[1], &caps[2], to, &caps[3]))
+        .into_owned()
+}
+
+#[derive(Debug)]
+pub struct VolumeOptions {
+    pub managed: ManagedVolumeStrategy,
+    /// Source storage location prefix to target prefix.
+    pub location_mapping: HashMap<String, String>,
+}
+
+impl VolumeOptions {
+    /// Rewrites `location` with the longest matching prefix, or keeps it as is.
+    fn map_location(&self, location: &str) -> String {
+        self.location_mapping
+            .iter()
+            .filter(|(from, _)| location.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len())
+            .map(|(from, to)| format!("{}{}", to, &location[from.len()..]))
+            .unwrap_or_else(|| location.to_string())
+    }
+
+    fn syncs(&self, volume: &Volume) -> bool {
+        !volume.is_managed() || self.managed != ManagedVolumeStrategy::Skip
+    }
+}
+
+#[derive(Debug)]
+pub struct MetadataOptions {
+    pub comments: bool,
+    pub tags: bool,
+}
+
+#[derive(Debug)]
+pub struct PermissionOptions {
+    pub sync_grants: bool,
+    pub sync_ownership: bool,
+    /// Source principal to target principal. Unmapped principals are kept as is.
+    pub principal_mapping: HashMap<String, String>,
+}
+
+impl PermissionOptions {
+    fn map_principal(&self, principal: &str) -> String {
+        self.principal_mapping
+            .get(principal)
+            .cloned()
+            .unwrap_or_else(|| principal.to_string())
+    }
+
+    fn map_grants(&self, grants: &Grants) -> Grants {
+        let mut mapped = Grants::new();
+        for (principal, privileges) in grants {
+            mapped
+                .entry(self.map_principal(principal))
+                .or_default()
+                .extend(privileges.iter().cloned());
+        }
+        mapped
+    }
+}
+
+/// Builds the grant, revoke and owner operations needed to make the target
+/// securable match the source. A `None` target is treated as having no grants
+/// and no owner, which is the state of a freshly created object.
+fn diff_permissions<'a>(
+    object: Object<'a>,
+    source: (Option<&String>, &Grants),
+    target: Option<(Option<&String>, &Grants)>,
+    options: &DiffOptions,
+) -> Vec<DiffNode<'a>> {
+    let Some(permissions) = &options.permissions else {
+        return vec![];
+    };
+    let (source_owner, source_grants) = source;
+    let empty_grants = Grants::new();
+    let (target_owner, target_grants) = target.unwrap_or((None, &empty_grants));
+    let mut nodes = vec![];
+
+    if permissions.sync_grants {
+        let empty_privileges = BTreeSet::new();
+        let wanted = permissions.map_grants(source_grants);
+        for (principal, privileges) in &wanted {
+            let current = target_grants.get(principal).unwrap_or(&empty_privileges);
+            let missing: Vec<String> = privileges.difference(current).cloned().collect();
+            if !missing.is_empty() {
+                nodes.push(DiffNode {
+                    operation: Some(Operation::Grant {
+                        object,
+                        principal: principal.clone(),
+                        privileges: missing,
+                    }),
+                    children: vec![],
+                });
+            }
+        }
+        for (principal, privileges) in target_grants {
+            let allowed = wanted.get(principal).unwrap_or(&empty_privileges);
+            let extra: Vec<String> = privileges.difference(allowed).cloned().collect();
+            if !extra.is_empty() {
+                nodes.push(DiffNode {
+                    operation: Some(Operation::Revoke {
+                        object,
+                        principal: principal.clone(),
+                        privileges: extra,
+                    }),
+                    children: vec![],
+                });
+            }
+        }
+    }
+
+    if permissions.sync_ownership {
+        if let Some(owner) = source_owner.map(|o| permissions.map_principal(o)) {
+            if target_owner != Some(&owner) {
+                // Ownership goes last so the grants above are still ours to give.
+                nodes.push(DiffNode {
+                    operation: Some(Operation::SetOwner { object, owner }),
+                    children: vec![],
+                });
+            }
+        }
+    }
+    nodes
+}
+
+/// Builds the comment and tag operations needed to make the target object's
+/// metadata match the source. As with permissions, a `None` target stands for
+/// an object that is about to be created.
+fn diff_metadata<'a>(
+    object: Object<'a>,
+    source: (Option<&String>, &Tags),
+    target: Option<(Option<&String>, &Tags)>,
+    options: &DiffOptions,
+) -> Vec<DiffNode<'a>> {
+    let Some(metadata) = &options.metadata else {
+        return vec![];
+    };
+    let (source_comment, source_tags) = source;
+    let empty_tags = Tags::new();
+    let (target_comment, target_tags) = target.unwrap_or((None, &empty_tags));
+    let mut nodes = vec![];
+
+    if metadata.comments && source_comment != target_comment {
+        nodes.push(DiffNode {
+            operation: Some(Operation::SetComment {
+                object,
+                comment: source_comment.cloned(),
+            }),
+            children: vec![],
+        });
+    }
+
+    if metadata.tags {
+        let changed: Vec<(String, String)> = source_tags
+            .iter()
+            .filter(|(key, value)| target_tags.get(*key) != Some(*value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let removed: Vec<String> = target_tags
+            .keys()
+            .filter(|key| !source_tags.contains_key(*key))
+            .cloned()
+            .collect();
+        if !removed.is_empty() {
+            nodes.push(DiffNode {
+                operation: Some(Operation::UnsetTags { object, keys: removed }),
+                children: vec![],
+            });
+        }
+        if !changed.is_empty() {
+            nodes.push(DiffNode {
+                operation: Some(Operation::SetTags { object, tags: changed }),
+                children: vec![],
+            });
+        }
+    }
+    nodes
+}
+
+/// Metadata operations for every source column of `source`. Columns only
+/// present in the target are left alone.
+fn diff_column_metadata<'a>(source: &'a Table, target: Option<&'a Table>, options: &DiffOptions) -> Vec<DiffNode<'a>> {
+    source
+        .columns
+        .iter()
+        .flat_map(|column| {
+            let other = target.and_then(|t| t.columns.iter().find(|c| c.name == column.name));
+            diff_metadata(
+                Object::Column(source, column),
+                (column.comment.as_ref(), &column.tags),
+                other.map(|c| (c.comment.as_ref(), &c.tags)),
+                options,
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Object<'a> {
     Catalog(&'a Catalog),
     Schema(&'a Schema),
     Table(&'a Table),
+    Column(&'a Table, &'a Column),
 }
 
 impl<'a> Object<'a> {
-    pub fn to_path(&self) -> String {
+    pub fn to_path(self) -> String {
         match self {
             Object::Catalog(catalog) => catalog.name.clone(),
             Object::Schema(schema) => format!("{}.{}", schema._catalog_name, schema._name),
             Object::Table(table) => format!("{}.{}.{}", table.catalog_name, table.schema_name, table.name),
+            Object::Column(table, column) => format!(
+                "{}.{}.{}.{}",
+                table.catalog_name, table.schema_name, table.name, column.name
+            ),
+        }
+    }
+
+    pub fn securable_type(&self) -> &'static str {
+        match self {
+            Object::Catalog(_) => "CATALOG",
+            Object::Schema(_) => "SCHEMA",
+            Object::Table(_) => "TABLE",
+            Object::Column(_, _) => "COLUMN",
         }
     }
 }
 
+/// A change that brings a pinned catalog closer to its source.
 #[derive(Debug)]
 pub enum Operation<'a> {
     CreateCatalog(&'a Catalog),
     CreateSchema(&'a Schema),
-    DropCatalog(&'a Catalog),
+    DropCatalog {
+        catalog: &'a Catalog,
+        reason: String,
+    },
     DropSchema(&'a Schema),
     DropTable(&'a Table),
     CloneTable {
         source: &'a Table,
         target: Option<&'a Table>, // None for new table, Some for existing table to replace
+        method: CloneMethod,
+        reason: CloneReason<'a>,
+    },
+    Grant {
+        object: Object<'a>,
+        principal: String,
+        privileges: Vec<String>,
+    },
+    Revoke {
+        object: Object<'a>,
+        principal: String,
+        privileges: Vec<String>,
+    },
+    SetOwner {
+        object: Object<'a>,
+        owner: String,
+    },
+    SetComment {
+        object: Object<'a>,
+        comment: Option<String>, // None clears the comment
+    },
+    SetTags {
+        object: Object<'a>,
+        tags: Vec<(String, String)>,
+    },
+    UnsetTags {
+        object: Object<'a>,
+        keys: Vec<String>,
+    },
+    CreateVolume {
+        source: &'a Volume,
+        target: Option<&'a Volume>, // Some for an existing volume to replace
+        location: Option<String>,   // None for managed volumes
+    },
+    DropVolume(&'a Volume),
+    CopyVolumeFiles(&'a Volume),
+    CreateFunction(&'a Function),
+    DropFunction(&'a Function),
+    /// Views, and materialized views or streaming tables recreated from their definition.
+    CreateFromDefinition {
+        source: &'a Table,
+        target: Option<&'a Table>, // Some for an existing table or view to replace
+    },
+    /// Copy of the current contents of a materialized view or streaming table
+    /// into a plain Delta table, as a subset or with masked columns when configured.
+    SnapshotTable {
+        source: &'a Table,
+        target: Option<&'a Table>,
+        spec: Option<CopySpec<'a>>,
+        reason: CloneReason<'a>,
+    },
+    SkipTable {
+        table: &'a Table,
+        reason: String,
+    },
+    /// CTAS of a sample or filtered subset of the source, with masked columns.
+    CopySelect {
+        source: &'a Table,
+        target: Option<&'a Table>,
+        spec: CopySpec<'a>,
+        reason: CloneReason<'a>,
     },
 }
 
+/// One operation of a diff, or a group without an operation, with the diffs of
+/// the objects below it.
 pub struct DiffNode<'a> {
     pub operation: Option<Operation<'a>>,
     pub children: Vec<DiffNode<'a>>,
@@ -131,147 +1039,407 @@ impl<'a> Debug for DiffNode<'a> {
     }
 }
 
+/// Compares a source object with its copy in a pinned catalog.
 pub trait DiffTree<'a> {
-    fn diff(&'a self, other: Option<&'a Self>) -> Option<DiffNode<'a>>;
+    fn diff(&'a self, other: Option<&'a Self>, options: &DiffOptions) -> Option<DiffNode<'a>>;
 }
 
 impl<'a> DiffTree<'a> for Catalog {
-    fn diff(&'a self, other: Option<&'a Catalog>) -> Option<DiffNode<'a>> {
+    fn diff(&'a self, other: Option<&'a Catalog>, options: &DiffOptions) -> Option<DiffNode<'a>> {
         match other {
             Some(other) => {
                 let mut children = vec![];
-                let a_keys = self.schemas.keys().collect::<HashSet<&String>>();
-                let b_keys = other.schemas.keys().collect::<HashSet<&String>>();
-
-                let only_a: Vec<&Schema> = a_keys
-                    .difference(&b_keys)
-                    .map(|k| self.schemas.get(k.as_str()).unwrap())
-                    .collect();
-                let only_b: Vec<&Schema> = b_keys
-                    .difference(&a_keys)
-                    .map(|k| other.schemas.get(k.as_str()).unwrap())
-                    .collect();
-                let shared: Vec<&Schema> = a_keys
-                    .intersection(&b_keys)
-                    .map(|k| self.schemas.get(k.as_str()).unwrap())
-                    .collect();
-
+                children.extend(diff_permissions(
+                    Object::Catalog(self),
+                    (self.owner.as_ref(), &self.grants),
+                    Some((other.owner.as_ref(), &other.grants)),
+                    options,
+                ));
+                children.extend(diff_metadata(
+                    Object::Catalog(self),
+                    (self.comment.as_ref(), &self.tags),
+                    Some((other.comment.as_ref(), &other.tags)),
+                    options,
+                ));
 
+                // Drops come first, then schemas in name order.
                 children.extend(
-                    shared
-                        .iter()
-                        .filter_map(|s| s.diff(other.schemas.get(s._name.as_str())))
+                    other
+                        .schemas
+                        .values()
+                        .filter(|s| !self.schemas.contains_key(&s._name))
+                        .map(|s| DiffNode {
+                            operation: Some(Operation::DropSchema(s)),
+                            children: vec![],
+                        }),
+                );
+                children.extend(
+                    self.schemas
+                        .values()
+                        .filter_map(|s| s.diff(other.schemas.get(&s._name), options)),
                 );
 
-                children.extend(only_a.iter().filter_map(|s| s.diff(None)));
-                children.extend(only_b.iter().map(|s| DiffNode {
-                    operation: Some(Operation::DropSchema(s)),
-                    children: vec![],
-                }));
-
-                if children.len() > 0 {
+                if !children.is_empty() {
                     return Some(DiffNode {
                         operation: None,
-                        children: children,
+                        children,
                     });
                 }
                 None
             }
             None => {
-                return Some(DiffNode {
+                let mut children = diff_permissions(Object::Catalog(self), (self.owner.as_ref(), &self.grants), None, options);
+                children.extend(diff_metadata(
+                    Object::Catalog(self),
+                    (self.comment.as_ref(), &self.tags),
+                    None,
+                    options,
+                ));
+                children.extend(self.schemas.values().filter_map(|v| v.diff(None, options)));
+                Some(DiffNode {
                     operation: Some(Operation::CreateCatalog(self)),
-                    children: self
-                        .schemas
-                        .iter()
-                        .filter_map(|(_k, v)| v.diff(None))
-                        .collect(),
-                });
+                    children,
+                })
             }
         }
     }
 }
 
 impl<'a> DiffTree<'a> for Schema {
-    fn diff(&'a self, other: Option<&'a Schema>) -> Option<DiffNode<'a>> {
+    fn diff(&'a self, other: Option<&'a Schema>, options: &DiffOptions) -> Option<DiffNode<'a>> {
         match other {
             Some(other) => {
                 let mut children = vec![];
-                let a_keys = self.tables.keys().collect::<HashSet<&String>>();
-                let b_keys = other.tables.keys().collect::<HashSet<&String>>();
-
-                let only_a: Vec<&Table> = a_keys
-                    .difference(&b_keys)
-                    .map(|k| self.tables.get(k.as_str()).unwrap())
-                    .collect();
-                let only_b: Vec<&Table> = b_keys
-                    .difference(&a_keys)
-                    .map(|k| other.tables.get(k.as_str()).unwrap())
-                    .collect();
-                let shared: Vec<&Table> = a_keys
-                    .intersection(&b_keys)
-                    .map(|k| self.tables.get(k.as_str()).unwrap())
-                    .collect();
+                children.extend(diff_permissions(
+                    Object::Schema(self),
+                    (self.owner.as_ref(), &self.grants),
+                    Some((other.owner.as_ref(), &other.grants)),
+                    options,
+                ));
+                children.extend(diff_metadata(
+                    Object::Schema(self),
+                    (self.comment.as_ref(), &self.tags),
+                    Some((other.comment.as_ref(), &other.tags)),
+                    options,
+                ));
 
+                // Drops come first, so a name is free before anything is created
+                // under it, then tables, volumes and functions in name order.
                 children.extend(
-                    shared
-                        .iter()
-                        .filter_map(|s| s.diff(other.tables.get(s.name.as_str())))
+                    other
+                        .tables
+                        .values()
+                        .filter(|t| !self.tables.contains_key(&t.name))
+                        .map(|t| DiffNode {
+                            operation: Some(Operation::DropTable(t)),
+                            children: vec![],
+                        }),
                 );
+                if let Some(volume_options) = &options.volumes {
+                    children.extend(
+                        other
+                            .volumes
+                            .values()
+                            .filter(|v| !self.volumes.contains_key(&v.name) && volume_options.syncs(v))
+                            .map(|v| DiffNode {
+                                operation: Some(Operation::DropVolume(v)),
+                                children: vec![],
+                            }),
+                    );
+                }
+                if options.functions {
+                    children.extend(
+                        other
+                            .functions
+                            .values()
+                            .filter(|f| !self.functions.contains_key(&f.name))
+                            .map(|f| DiffNode {
+                                operation: Some(Operation::DropFunction(f)),
+                                children: vec![],
+                            }),
+                    );
+                }
 
-                children.extend(only_a.iter().filter_map(|s| s.diff(None)));
-                children.extend(only_b.iter().map(|t| DiffNode {
-                    operation: Some(Operation::DropTable(t)),
-                    children: vec![],
-                }));
+                children.extend(
+                    self.tables
+                        .values()
+                        .filter_map(|t| t.diff(other.tables.get(&t.name), options)),
+                );
+                if options.volumes.is_some() {
+                    children.extend(
+                        self.volumes
+                            .values()
+                            .filter_map(|v| v.diff(other.volumes.get(&v.name), options)),
+                    );
+                }
+                if options.functions {
+                    children.extend(
+                        self.functions
+                            .values()
+                            .filter_map(|f| f.diff(other.functions.get(&f.name), options)),
+                    );
+                }
 
-                if children.len() > 0 {
+                if !children.is_empty() {
                     return Some(DiffNode {
                         operation: None,
-                        children: children,
+                        children,
                     });
                 }
                 None
             }
             None => {
-                return Some(DiffNode {
+                let mut children =
+                    diff_permissions(Object::Schema(self), (self.owner.as_ref(), &self.grants), None, options);
+                children.extend(diff_metadata(
+                    Object::Schema(self),
+                    (self.comment.as_ref(), &self.tags),
+                    None,
+                    options,
+                ));
+                children.extend(self.tables.values().filter_map(|v| v.diff(None, options)));
+                children.extend(self.volumes.values().filter_map(|v| v.diff(None, options)));
+                children.extend(self.functions.values().filter_map(|f| f.diff(None, options)));
+                Some(DiffNode {
                     operation: Some(Operation::CreateSchema(self)),
-                    children: self
-                        .tables
-                        .iter()
-                        .filter_map(|(_k, v)| v.diff(None))
-                        .collect(),
-                });
+                    children,
+                })
             }
         }
     }
 }
 
+impl Table {
+    /// Permission and metadata operations for this table and its columns
+    /// against `target`, or against a freshly created table when `None`.
+    fn diff_attributes<'a>(&'a self, target: Option<&'a Table>, options: &DiffOptions) -> Vec<DiffNode<'a>> {
+        let mut children = diff_permissions(
+            Object::Table(self),
+            (self.owner.as_ref(), &self.grants),
+            target.map(|t| (t.owner.as_ref(), &t.grants)),
+            options,
+        );
+        children.extend(diff_metadata(
+            Object::Table(self),
+            (self.comment.as_ref(), &self.tags),
+            target.map(|t| (t.comment.as_ref(), &t.tags)),
+            options,
+        ));
+        children.extend(diff_column_metadata(self, target, options));
+        children
+    }
+}
+
 impl<'a> DiffTree<'a> for Table {
-    fn diff(&'a self, other: Option<&'a Table>) -> Option<DiffNode<'a>> {
+    fn diff(&'a self, other: Option<&'a Table>, options: &DiffOptions) -> Option<DiffNode<'a>> {
+        match self.kind() {
+            TableKind::Table => {
+                if let Some(spec) = options.copy_spec_for(self) {
+                    return self.diff_copy(other, spec, options);
+                }
+            }
+            TableKind::View => return self.diff_definition(other, options),
+            TableKind::MaterializedView => return self.diff_derived(other, options.materialized_views, options),
+            TableKind::StreamingTable => return self.diff_derived(other, options.streaming_tables, options),
+        }
         match other {
+            // Identity and schema changes re-clone right away, however fresh the target is.
+            Some(other) => match self.clone_reason(other, options.clone_method) {
+                // The re-clone drops the target, so its grants, owner and tags go with it.
+                Some(reason) => Some(DiffNode {
+                    operation: Some(Operation::CloneTable {
+                        source: self,
+                        target: Some(other),
+                        method: options.clone_method,
+                        reason,
+                    }),
+                    children: self.diff_attributes(None, options),
+                }),
+                None => self.attributes_only(other, options),
+            },
+            None => Some(DiffNode {
+                operation: Some(Operation::CloneTable {
+                    source: self,
+                    target: None,
+                    method: options.clone_method,
+                    reason: CloneReason::New,
+                }),
+                children: self.diff_attributes(None, options),
+            }),
+        }
+    }
+}
+
+impl Table {
+    fn attributes_only<'a>(&'a self, other: &'a Table, options: &DiffOptions) -> Option<DiffNode<'a>> {
+        let children = self.diff_attributes(Some(other), options);
+        if children.is_empty() {
+            return None;
+        }
+        Some(DiffNode {
+            operation: None,
+            children,
+        })
+    }
+
+    /// Views, and derived tables set to be recreated, hold no data of their own,
+    /// so they are compared by definition instead of by staleness.
+    fn diff_definition<'a>(&'a self, other: Option<&'a Table>, options: &DiffOptions) -> Option<DiffNode<'a>> {
+        let existing = other.filter(|o| o.kind() == self.kind());
+        let unchanged = existing.is_some_and(|o| {
+            o.view_definition
+                == self
+                    .view_definition
+                    .as_deref()
+                    .map(|d| rewrite_catalog_references(d, &self.catalog_name, &o.catalog_name))
+        });
+        if unchanged {
+            return self.attributes_only(other.unwrap(), options);
+        }
+        // Replacing an object of the same kind keeps its grants and tags, replacing another kind does not.
+        Some(DiffNode {
+            operation: Some(Operation::CreateFromDefinition { source: self, target: other }),
+            children: self.diff_attributes(existing, options),
+        })
+    }
+
+    /// Subset and masked copies are refreshed by the source `updated_at` recorded when
+    /// they were made, as the CTAS target's own timestamps say nothing about the source.
+    fn diff_copy<'a>(&'a self, other: Option<&'a Table>, spec: CopySpec<'a>, options: &DiffOptions) -> Option<DiffNode<'a>> {
+        let reason = match other {
+            None => CloneReason::New,
+            Some(other) if self.source_recreated(other) => CloneReason::SourceRecreated {
+                cloned_from: other.source_table_id().unwrap(),
+            },
+            Some(other) if other.kind() != TableKind::Table => CloneReason::KindChanged,
+            Some(other) if !spec.matches(other) => CloneReason::CopySpecChanged,
             Some(other) => {
-                if self.updated_at - other.updated_at > Duration::days(1) {
-                    // TODO: Make duration configurable
-                    Some(DiffNode {
-                        operation: Some(Operation::CloneTable {
-                            source: self,
-                            target: Some(other),
-                        }),
-                        children: vec![],
-                    })
-                } else {
-                    None
+                let copied_at = other
+                    .properties
+                    .get(SOURCE_UPDATED_AT_PROPERTY)
+                    .and_then(|millis| millis.parse().ok())
+                    .and_then(DateTime::from_timestamp_millis);
+                // TODO: Make duration configurable
+                match copied_at {
+                    Some(copied_at) if self.updated_at - copied_at <= Duration::days(1) => {
+                        return self.attributes_only(other, options)
+                    }
+                    _ => CloneReason::Stale,
                 }
             }
-            None => {
-                return Some(DiffNode {
-                    operation: Some(Operation::CloneTable {
+        };
+        Some(DiffNode {
+            operation: Some(Operation::CopySelect {
+                source: self,
+                target: other,
+                spec,
+                reason,
+            }),
+            children: self.diff_attributes(None, options),
+        })
+    }
+
+    /// Materialized views and streaming tables, synced as configured for their kind.
+    fn diff_derived<'a>(
+        &'a self,
+        other: Option<&'a Table>,
+        strategy: DerivedTableStrategy,
+        options: &DiffOptions,
+    ) -> Option<DiffNode<'a>> {
+        let skip = |reason: String| {
+            Some(DiffNode {
+                operation: Some(Operation::SkipTable { table: self, reason }),
+                children: vec![],
+            })
+        };
+        match strategy {
+            DerivedTableStrategy::Skip => skip(format!("{} is not synced", self.table_type)),
+            DerivedTableStrategy::Recreate if self.view_definition.is_none() => {
+                skip(format!("{} has no definition to recreate from", self.table_type))
+            }
+            DerivedTableStrategy::Recreate => self.diff_definition(other, options),
+            DerivedTableStrategy::Snapshot => {
+                // Snapshots are plain copies, so subsets and masking apply to them as to tables.
+                let spec = options.copy_spec_for(self);
+                let reason = match other {
+                    None => CloneReason::New,
+                    Some(other) if self.source_recreated(other) => CloneReason::SourceRecreated {
+                        cloned_from: other.source_table_id().unwrap(),
+                    },
+                    Some(other) if other.kind() != TableKind::Table => CloneReason::KindChanged,
+                    Some(other) if !spec.as_ref().unwrap_or(&CopySpec::default()).matches(other) => {
+                        CloneReason::CopySpecChanged
+                    }
+                    Some(other) if self.is_stale(other, CloneMethod::Ctas) => CloneReason::Stale,
+                    Some(other) => return self.attributes_only(other, options),
+                };
+                Some(DiffNode {
+                    operation: Some(Operation::SnapshotTable {
                         source: self,
-                        target: None,
+                        target: other,
+                        spec,
+                        reason,
                     }),
-                    children: vec![],
-                });
+                    children: self.diff_attributes(None, options),
+                })
+            }
+        }
+    }
+}
+
+impl<'a> DiffTree<'a> for Function {
+    fn diff(&'a self, other: Option<&'a Function>, options: &DiffOptions) -> Option<DiffNode<'a>> {
+        if !options.functions {
+            return None;
+        }
+        if let Some(other) = other {
+            let definition = rewrite_catalog_references(&self.definition, &self.catalog_name, &other.catalog_name);
+            if self.parameters == other.parameters
+                && self.returns == other.returns
+                && self.language == other.language
+                && self.deterministic == other.deterministic
+                && self.comment == other.comment
+                && definition == other.definition
+            {
+                return None;
+            }
+        }
+        Some(DiffNode {
+            operation: Some(Operation::CreateFunction(self)),
+            children: vec![],
+        })
+    }
+}
+
+impl<'a> DiffTree<'a> for Volume {
+    fn diff(&'a self, other: Option<&'a Volume>, options: &DiffOptions) -> Option<DiffNode<'a>> {
+        let volume_options = options.volumes.as_ref()?;
+        if !volume_options.syncs(self) {
+            return None;
+        }
+        let location = match self.is_managed() {
+            true => None,
+            false => self.storage_location.as_deref().map(|l| volume_options.map_location(l)),
+        };
+        if let Some(other) = other {
+            if other.volume_type == self.volume_type && (self.is_managed() || other.storage_location == location) {
+                return None;
             }
         }
+        let children = match self.is_managed() && volume_options.managed == ManagedVolumeStrategy::CopyFiles {
+            true => vec![DiffNode {
+                operation: Some(Operation::CopyVolumeFiles(self)),
+                children: vec![],
+            }],
+            false => vec![],
+        };
+        Some(DiffNode {
+            operation: Some(Operation::CreateVolume {
+                source: self,
+                target: other,
+                location,
+            }),
+            children,
+        })
     }
 }
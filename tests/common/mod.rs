@@ -0,0 +1,410 @@
+//! In-process mock of a Databricks workspace for end-to-end tests, serving the
+//! Unity Catalog and statement execution endpoints from declarative fixture files.
+//!
+//! A fixture lists responses by method, path and exact query, and the outcome of
+//! statements by a substring of their text:
+//!
+//! ```yaml
+//! requests:
+//!   - path: /api/2.1/unity-catalog/catalogs/prod
+//!     responses:
+//!       - status: 429
+//!       - body: { name: prod }
+//! statements:
+//!   - match: CREATE SCHEMA dev.
+//!     state: FAILED
+//!     message: PERMISSION_DENIED
+//! ```
+//!
+//! The same fixtures can describe an open-source Unity Catalog server, which
+//! serves the same endpoints without statements, see [`MockWorkspace::oss_config`].
+//!
+//! Responses of a request are served in order and the last one repeats. Requests
+//! without a fixture get a 404, so objects that do not exist are simply left out.
+//! Statements without a fixture succeed, and statements in state `RUNNING` keep
+//! running until they are cancelled.
+//!
+//! Setting `DBSYNC_RECORD_HOST` and `DBSYNC_RECORD_TOKEN` records a fixture instead:
+//! every request is forwarded to that workspace and the fixture file is rewritten
+//! with its responses when the mock is dropped. Statements run for real while
+//! recording, so point `warehouse_id` at a scratch workspace.
+
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use dbsync::{metastore, Client, CrawlMode, Crawler, SyncConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub requests: Vec<FixtureRequest>,
+    #[serde(default)]
+    pub statements: Vec<FixtureStatement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub query: BTreeMap<String, String>,
+    pub responses: Vec<FixtureResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureResponse {
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureStatement {
+    /// Substring of the statement text.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default = "default_state")]
+    pub state: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+fn default_state() -> String {
+    "SUCCEEDED".to_string()
+}
+
+impl Fixture {
+    /// Reads `tests/fixtures/<name>.yaml`.
+    pub fn load(name: &str) -> Self {
+        let path = fixture_path(name);
+        let yaml = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e));
+        serde_yaml::from_str(&yaml).unwrap_or_else(|e| panic!("parsing {}: {}", path.display(), e))
+    }
+
+    /// Serves `status` the first `times` times `path` is requested, before its fixture responses.
+    pub fn fail_first(&mut self, path: &str, status: u16, times: usize) {
+        let request = self
+            .requests
+            .iter_mut()
+            .find(|r| r.path == path)
+            .unwrap_or_else(|| panic!("no fixture for {}", path));
+        let failure = FixtureResponse {
+            status,
+            body: json!({"error_code": "MOCKED", "message": format!("mocked {}", status)}),
+        };
+        request.responses.splice(0..0, std::iter::repeat_n(failure, times));
+    }
+
+    /// Fails every statement containing `pattern`.
+    pub fn fail_statement(&mut self, pattern: &str, message: &str) {
+        self.statements.insert(
+            0,
+            FixtureStatement {
+                pattern: pattern.to_string(),
+                state: "FAILED".to_string(),
+                message: Some(message.to_string()),
+            },
+        );
+    }
+
+    /// Keeps every statement containing `pattern` running until it is cancelled.
+    pub fn keep_running(&mut self, pattern: &str) {
+        self.statements.insert(
+            0,
+            FixtureStatement {
+                pattern: pattern.to_string(),
+                state: "RUNNING".to_string(),
+                message: None,
+            },
+        );
+    }
+}
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(format!("{}.yaml", name))
+}
+
+/// Where requests go while recording, and where the fixture is written.
+struct Recorder {
+    upstream: String,
+    token: String,
+    client: reqwest::Client,
+    path: PathBuf,
+    /// Statements still running upstream, by statement id.
+    pending: Mutex<HashMap<String, String>>,
+}
+
+struct Shared {
+    fixture: Mutex<Fixture>,
+    /// Responses served so far per fixture request.
+    served: Mutex<HashMap<usize, usize>>,
+    /// Every request as `METHOD /path?query`, in arrival order.
+    requests: Mutex<Vec<String>>,
+    /// Text of every statement, in the order they were run.
+    statements: Mutex<Vec<String>>,
+    /// State and error message of every statement by statement id.
+    states: Mutex<HashMap<String, (String, Option<String>)>>,
+    /// Ids of the statements that were cancelled.
+    cancelled: Mutex<Vec<String>>,
+    recorder: Option<Recorder>,
+}
+
+pub struct MockWorkspace {
+    pub url: String,
+    shared: Arc<Shared>,
+    dir: tempfile::TempDir,
+}
+
+impl MockWorkspace {
+    /// Serves `tests/fixtures/<name>.yaml`, or records it when `DBSYNC_RECORD_HOST` is set.
+    pub async fn start(name: &str) -> Self {
+        match (std::env::var("DBSYNC_RECORD_HOST"), std::env::var("DBSYNC_RECORD_TOKEN")) {
+            (Ok(host), Ok(token)) => Self::record(&format!("https://{}", host), &token, fixture_path(name)).await,
+            _ => Self::serve(Fixture::load(name)).await,
+        }
+    }
+
+    pub async fn serve(fixture: Fixture) -> Self {
+        Self::spawn(fixture, None).await
+    }
+
+    /// Forwards every request to `upstream` and writes what it answers to `path` on drop.
+    pub async fn record(upstream: &str, token: &str, path: PathBuf) -> Self {
+        let recorder = Recorder {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            client: reqwest::Client::new(),
+            path,
+            pending: Mutex::new(HashMap::new()),
+        };
+        Self::spawn(Fixture::default(), Some(recorder)).await
+    }
+
+    async fn spawn(fixture: Fixture, recorder: Option<Recorder>) -> Self {
+        let shared = Arc::new(Shared {
+            fixture: Mutex::new(fixture),
+            served: Mutex::new(HashMap::new()),
+            requests: Mutex::new(vec![]),
+            statements: Mutex::new(vec![]),
+            states: Mutex::new(HashMap::new()),
+            cancelled: Mutex::new(vec![]),
+            recorder,
+        });
+        let router = Router::new().fallback(handle).with_state(shared.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        Self {
+            url,
+            shared,
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    /// A config for this workspace with the given `catalogs` entries, as YAML.
+    pub fn config(&self, catalogs: &str) -> SyncConfig {
+        let yaml = format!("host: {}\npat: token\nwarehouse_id: warehouse\ncatalogs:\n{}", self.url, catalogs);
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    /// A config for this mock as an open-source Unity Catalog server without
+    /// authentication, with its host given as `host:port` without a scheme.
+    pub fn oss_config(&self, catalogs: &str) -> SyncConfig {
+        let host = self.url.strip_prefix("http://").unwrap();
+        let yaml = format!("host: {}\nmetastore: oss\ncatalogs:\n{}", host, catalogs);
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    /// A crawler for this workspace, without a crawl cache.
+    pub fn crawler(&self) -> Crawler {
+        Crawler {
+            backend: Arc::new(Client::new(&self.url, "token")),
+            concurrency: 4,
+            mode: CrawlMode::Full,
+            cache_path: self.dir.path().join("cache.json"),
+            no_cache: true,
+        }
+    }
+
+    /// A crawler for the backend `config` points at, without a crawl cache.
+    pub fn crawler_for(&self, config: &SyncConfig) -> Crawler {
+        Crawler {
+            backend: metastore::from_config(config),
+            ..self.crawler()
+        }
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    pub fn statements(&self) -> Vec<String> {
+        self.shared.statements.lock().unwrap().clone()
+    }
+
+    pub fn cancelled(&self) -> Vec<String> {
+        self.shared.cancelled.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockWorkspace {
+    fn drop(&mut self) {
+        if let Some(recorder) = &self.shared.recorder {
+            let yaml = serde_yaml::to_string(&*self.shared.fixture.lock().unwrap()).unwrap();
+            std::fs::write(&recorder.path, yaml).unwrap();
+        }
+    }
+}
+
+async fn handle(
+    State(shared): State<Arc<Shared>>,
+    method: Method,
+    uri: Uri,
+    Query(query): Query<BTreeMap<String, String>>,
+    body: Bytes,
+) -> Response {
+    shared.requests.lock().unwrap().push(format!("{} {}", method, uri));
+    if let Some(recorder) = &shared.recorder {
+        return forward(&shared, recorder, method, uri, query, body).await;
+    }
+    if method == Method::POST && uri.path() == "/api/2.0/sql/statements" {
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let statement = request["statement"].as_str().unwrap().to_string();
+        let fixture = shared.fixture.lock().unwrap();
+        let (state, message) = fixture
+            .statements
+            .iter()
+            .find(|s| statement.contains(&s.pattern))
+            .map(|s| (s.state.clone(), s.message.clone()))
+            .unwrap_or_else(|| (default_state(), None));
+        let mut statements = shared.statements.lock().unwrap();
+        statements.push(statement);
+        let id = format!("statement-{}", statements.len());
+        shared.states.lock().unwrap().insert(id.clone(), (state, message));
+        return statement_response(&shared, &id);
+    }
+    if let Some(id) = uri.path().strip_prefix("/api/2.0/sql/statements/") {
+        if let Some(id) = id.strip_suffix("/cancel") {
+            if let Some((state, _)) = shared.states.lock().unwrap().get_mut(id) {
+                *state = "CANCELED".to_string();
+            }
+            shared.cancelled.lock().unwrap().push(id.to_string());
+            return Json(json!({})).into_response();
+        }
+        return statement_response(&shared, id);
+    }
+
+    let fixture = shared.fixture.lock().unwrap();
+    let Some(index) = fixture
+        .requests
+        .iter()
+        .position(|r| r.method == method.as_str() && r.path == uri.path() && r.query == query)
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error_code": "RESOURCE_DOES_NOT_EXIST", "message": format!("no fixture for {}", uri)})),
+        )
+            .into_response();
+    };
+    let responses = &fixture.requests[index].responses;
+    let mut served = shared.served.lock().unwrap();
+    let count = served.entry(index).or_default();
+    let response = &responses[(*count).min(responses.len() - 1)];
+    *count += 1;
+    (StatusCode::from_u16(response.status).unwrap(), Json(response.body.clone())).into_response()
+}
+
+fn statement_response(shared: &Shared, id: &str) -> Response {
+    let Some((state, message)) = shared.states.lock().unwrap().get(id).cloned() else {
+        return (StatusCode::NOT_FOUND, Json(json!({"error_code": "NOT_FOUND"}))).into_response();
+    };
+    Json(json!({
+        "statement_id": id,
+        "status": {"state": state, "error": message.map(|m| json!({"message": m}))},
+    }))
+    .into_response()
+}
+
+async fn forward(
+    shared: &Shared,
+    recorder: &Recorder,
+    method: Method,
+    uri: Uri,
+    query: BTreeMap<String, String>,
+    body: Bytes,
+) -> Response {
+    let response = recorder
+        .client
+        .request(method.clone(), format!("{}{}", recorder.upstream, uri.path()))
+        .bearer_auth(&recorder.token)
+        .query(&query)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.clone())
+        .send()
+        .await
+        .unwrap();
+    let status = response.status().as_u16();
+    let bytes = response.bytes().await.unwrap();
+    let value: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+
+    let mut fixture = shared.fixture.lock().unwrap();
+    if uri.path().ends_with("/cancel") {
+        // Cancelling only changes the state a statement is later recorded with.
+    } else if uri.path().starts_with("/api/2.0/sql/statements") {
+        // Statements are recorded by their final state, not by request.
+        let mut pending = recorder.pending.lock().unwrap();
+        let id = value["statement_id"].as_str().unwrap_or_default().to_string();
+        let statement = match method {
+            Method::POST => serde_json::from_slice::<Value>(&body).unwrap()["statement"].as_str().unwrap().to_string(),
+            _ => pending.remove(&id).unwrap_or_default(),
+        };
+        match value["status"]["state"].as_str() {
+            Some("PENDING" | "RUNNING") => {
+                pending.insert(id, statement);
+            }
+            Some(state) => {
+                shared.statements.lock().unwrap().push(statement.clone());
+                fixture.statements.push(FixtureStatement {
+                    pattern: statement,
+                    state: state.to_string(),
+                    message: value["status"]["error"]["message"].as_str().map(str::to_string),
+                });
+            }
+            None => {}
+        }
+    } else {
+        let recorded = FixtureResponse { status, body: value };
+        let path = uri.path().to_string();
+        match fixture
+            .requests
+            .iter_mut()
+            .find(|r| r.method == method.as_str() && r.path == path && r.query == query)
+        {
+            Some(request) => request.responses.push(recorded),
+            None => fixture.requests.push(FixtureRequest {
+                method: method.to_string(),
+                path,
+                query,
+                responses: vec![recorded],
+            }),
+        }
+    }
+    (StatusCode::from_u16(status).unwrap(), bytes).into_response()
+}
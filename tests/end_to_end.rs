@@ -0,0 +1,336 @@
+mod common;
+
+use common::{Fixture, FixtureRequest, FixtureResponse, MockWorkspace};
+use dbsync::apply::{Limits, Status};
+use dbsync::journal::Journal;
+use dbsync::plan::load_catalogs;
+use dbsync::report::Run;
+use dbsync::{Plan, SyncConfig};
+use serde_json::json;
+use std::time::Duration;
+
+const ENTRIES: &str = "
+  - catalog: prod
+    pinned_catalogs: [staging, dev]
+";
+
+const TABLES: &str = "/api/2.1/unity-catalog/tables";
+
+async fn plan(workspace: &MockWorkspace, config: &SyncConfig) -> anyhow::Result<Plan> {
+    Plan::new(&workspace.crawler(), &config.catalogs, &[]).await
+}
+
+fn statements(run: &Run) -> Vec<&str> {
+    run.queries.iter().flat_map(|q| &q.statements).map(String::as_str).collect()
+}
+
+/// Statuses of the queries that run statements, leaving out the ones that only group others.
+fn statuses(run: &Run) -> Vec<Status> {
+    run.queries
+        .iter()
+        .zip(&run.outcomes)
+        .filter(|(query, _)| !query.statements.is_empty())
+        .map(|(_, outcome)| outcome.status)
+        .collect()
+}
+
+#[tokio::test]
+async fn plans_pinned_catalogs_from_paginated_listings() {
+    let workspace = MockWorkspace::start("workspace").await;
+    let config = workspace.config(ENTRIES);
+    let plan = plan(&workspace, &config).await.unwrap();
+
+    assert!(workspace.requests().iter().any(|r| r.contains("page_token=page+2%2F2")));
+    let [staging, dev] = &plan.runs[..] else { panic!("expected two runs") };
+    assert_eq!((staging.source.as_str(), staging.target.as_str()), ("prod", "staging"));
+    assert_eq!(
+        statements(staging),
+        [
+            "CREATE TABLE staging.sales.customers SHALLOW CLONE prod.sales.customers",
+            "ALTER TABLE staging.sales.customers SET TBLPROPERTIES ('dbsync.source_table_id' = '11111111-0000-0000-0000-000000000002')",
+        ]
+    );
+    assert_eq!(
+        statements(dev),
+        [
+            "CREATE CATALOG dev",
+            "CREATE SCHEMA dev.sales",
+            "CREATE TABLE dev.sales.customers SHALLOW CLONE prod.sales.customers",
+            "ALTER TABLE dev.sales.customers SET TBLPROPERTIES ('dbsync.source_table_id' = '11111111-0000-0000-0000-000000000002')",
+            "CREATE TABLE dev.sales.orders SHALLOW CLONE prod.sales.orders",
+            "ALTER TABLE dev.sales.orders SET TBLPROPERTIES ('dbsync.source_table_id' = '11111111-0000-0000-0000-000000000001')",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn applies_plans_through_the_statement_api() {
+    let workspace = MockWorkspace::start("workspace").await;
+    let config = workspace.config(ENTRIES);
+    let mut plan = plan(&workspace, &config).await.unwrap();
+    plan.apply(workspace.crawler().backend.as_ref(), "warehouse").await;
+
+    assert!(!plan.failed());
+    let planned: Vec<&str> = plan.runs.iter().flat_map(statements).collect();
+    assert_eq!(workspace.statements(), planned);
+    for run in &plan.runs {
+        assert!(statuses(run).iter().all(|status| *status == Status::Succeeded));
+    }
+}
+
+#[tokio::test]
+async fn a_failed_statement_stops_only_its_pinned_catalog() {
+    let mut fixture = Fixture::load("workspace");
+    fixture.fail_statement("CREATE TABLE dev.sales.customers", "PERMISSION_DENIED: no CREATE TABLE on dev.sales");
+    let workspace = MockWorkspace::serve(fixture).await;
+    let config = workspace.config(ENTRIES);
+    let mut plan = plan(&workspace, &config).await.unwrap();
+    plan.apply(workspace.crawler().backend.as_ref(), "warehouse").await;
+
+    assert!(plan.failed());
+    let [staging, dev] = &plan.runs[..] else { panic!("expected two runs") };
+    assert_eq!(statuses(staging), [Status::Succeeded]);
+    assert_eq!(statuses(dev), [Status::Succeeded, Status::Succeeded, Status::Failed, Status::NotRun]);
+    let error = dev.outcomes.iter().find_map(|o| o.error.as_deref()).unwrap();
+    assert!(error.contains("PERMISSION_DENIED"), "{}", error);
+    assert!(!workspace.statements().iter().any(|s| s.starts_with("CREATE TABLE dev.sales.orders")));
+}
+
+#[tokio::test]
+async fn retries_rate_limited_requests() {
+    let mut fixture = Fixture::load("workspace");
+    fixture.fail_first("/api/2.1/unity-catalog/catalogs/prod", 429, 1);
+    let workspace = MockWorkspace::serve(fixture).await;
+    let config = workspace.config(ENTRIES);
+    let plan = plan(&workspace, &config).await.unwrap();
+
+    let requested = workspace.requests().iter().filter(|r| r.ends_with("/catalogs/prod")).count();
+    assert_eq!(requested, 2);
+    assert_eq!(plan.runs[0].queries.iter().flat_map(|q| &q.statements).count(), 2);
+}
+
+#[tokio::test]
+async fn a_failed_listing_fails_the_plan() {
+    let mut fixture = Fixture::load("workspace");
+    fixture.fail_first(TABLES, 403, 1);
+    let workspace = MockWorkspace::serve(fixture).await;
+    let config = workspace.config(ENTRIES);
+
+    // Planning from a partial crawl would clone every table it missed.
+    let error = plan(&workspace, &config).await.err().unwrap();
+    assert!(format!("{:#}", error).contains("403 Forbidden"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn a_missing_pinned_catalog_is_created() {
+    // The fixture has no dev catalog, so getting it answers 404.
+    let workspace = MockWorkspace::start("workspace").await;
+    let config = workspace.config(
+        "
+  - catalog: prod
+    pinned_catalogs: [dev]
+",
+    );
+    let plan = plan(&workspace, &config).await.unwrap();
+
+    assert!(workspace.requests().iter().any(|r| r.ends_with("/catalogs/dev")));
+    assert!(!workspace.requests().iter().any(|r| r.contains("catalog_name=dev")));
+    let [dev] = &plan.runs[..] else { panic!("expected one run") };
+    assert_eq!(statements(dev)[..2], ["CREATE CATALOG dev", "CREATE SCHEMA dev.sales"]);
+}
+
+#[tokio::test]
+async fn a_missing_source_catalog_fails_the_plan() {
+    let mut fixture = Fixture::load("workspace");
+    fixture.requests.retain(|r| r.path != "/api/2.1/unity-catalog/catalogs/prod");
+    let workspace = MockWorkspace::serve(fixture).await;
+    let config = workspace.config(ENTRIES);
+
+    let error = plan(&workspace, &config).await.err().unwrap();
+    assert_eq!(format!("{:#}", error), "source catalog prod not found");
+}
+
+#[tokio::test]
+async fn plans_from_an_open_source_unity_catalog_server() {
+    // Recording talks to Databricks, so this fixture is only ever served.
+    let workspace = MockWorkspace::serve(Fixture::load("oss")).await;
+    let config = workspace.oss_config(
+        "
+  - catalog: unity
+    pinned_catalogs: [unity_dev]
+",
+    );
+    let unity_catalog = load_catalogs(&workspace.crawler_for(&config), &config.catalogs, &[])
+        .await
+        .unwrap();
+
+    assert!(workspace.requests().iter().any(|r| r.contains("page_token=2")));
+    // Tables that were never updated take their creation as their last update.
+    let tables = &unity_catalog.catalogs["unity"].schemas["default"].tables;
+    assert_eq!(tables["marksheet"].updated_at.timestamp_millis(), 1721234405334);
+    assert_eq!(tables["numbers"].updated_at.timestamp_millis(), 1750000000000);
+
+    let plan = Plan::from_catalogs(&config.catalogs, &unity_catalog).unwrap();
+    let [dev] = &plan.runs[..] else { panic!("expected one run") };
+    assert_eq!(
+        statements(dev),
+        [
+            "CREATE SCHEMA unity_dev.default",
+            "CREATE TABLE unity_dev.default.marksheet SHALLOW CLONE unity.default.marksheet",
+            "ALTER TABLE unity_dev.default.marksheet SET TBLPROPERTIES ('dbsync.source_table_id' = 'c389adfa-5c8f-497b-8f70-26c2cca4976d')",
+            "CREATE TABLE unity_dev.default.numbers SHALLOW CLONE unity.default.numbers",
+            "ALTER TABLE unity_dev.default.numbers SET TBLPROPERTIES ('dbsync.source_table_id' = '32025924-be53-4d67-ac39-501a86046c01')",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn recorded_fixtures_replay_the_same_plan() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("recorded.yaml");
+    let upstream = MockWorkspace::start("workspace").await;
+    let config = upstream.config(ENTRIES);
+    let expected = plan(&upstream, &config).await.unwrap();
+    {
+        let recorder = MockWorkspace::record(&upstream.url, "token", path.clone()).await;
+        let mut recorded = plan(&recorder, &config).await.unwrap();
+        recorded.apply(recorder.crawler().backend.as_ref(), "warehouse").await;
+        assert!(!recorded.failed());
+    }
+
+    let yaml = std::fs::read_to_string(&path).unwrap();
+    let replay = MockWorkspace::serve(serde_yaml::from_str(&yaml).unwrap()).await;
+    let mut replayed = plan(&replay, &config).await.unwrap();
+    replayed.apply(replay.crawler().backend.as_ref(), "warehouse").await;
+    for (expected, replayed) in expected.runs.iter().zip(&replayed.runs) {
+        assert_eq!(statements(expected), statements(replayed));
+    }
+    assert_eq!(replay.statements(), upstream.statements());
+}
+
+/// Adds what applying the workspace fixture creates up to the failing clone of
+/// dev.sales.customers: the customers clone in staging and the empty dev.sales.
+fn partially_applied(fixture: &mut Fixture) {
+    let staging = fixture
+        .requests
+        .iter_mut()
+        .find(|r| r.path == TABLES && r.query.get("catalog_name").is_some_and(|c| c == "staging"))
+        .unwrap();
+    staging.responses[0].body["tables"].as_array_mut().unwrap().push(json!({
+        "name": "customers",
+        "table_id": "22222222-0000-0000-0000-000000000002",
+        "catalog_name": "staging",
+        "schema_name": "sales",
+        "table_type": "MANAGED",
+        "data_source_format": "DELTA",
+        "updated_at": 1760000000000i64,
+        "updated_by": "dbsync@example.com",
+        "properties": {"dbsync.source_table_id": "11111111-0000-0000-0000-000000000002"},
+        "columns": [
+            {"name": "id", "type_text": "bigint", "nullable": false, "position": 0},
+            {"name": "name", "type_text": "string", "position": 1},
+        ],
+    }));
+    let dev = [
+        ("/api/2.1/unity-catalog/catalogs/dev", vec![], json!({"name": "dev", "catalog_type": "MANAGED_CATALOG"})),
+        (
+            "/api/2.1/unity-catalog/schemas",
+            vec![("catalog_name", "dev")],
+            json!({"schemas": [{"name": "sales", "catalog_name": "dev"}]}),
+        ),
+        (TABLES, vec![("catalog_name", "dev"), ("schema_name", "sales")], json!({"tables": []})),
+    ];
+    for (path, query, body) in dev {
+        fixture.requests.push(FixtureRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: query.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            responses: vec![FixtureResponse { status: 200, body }],
+        });
+    }
+}
+
+#[tokio::test]
+async fn resumes_an_interrupted_apply_from_its_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut fixture = Fixture::load("workspace");
+    fixture.fail_statement("CREATE TABLE dev.sales.customers", "warehouse restarted");
+    let failing = MockWorkspace::serve(fixture).await;
+    let config = failing.config(ENTRIES);
+    let mut journal = Journal::new(plan(&failing, &config).await.unwrap(), dir.path()).unwrap();
+    journal.apply(failing.crawler().backend.as_ref(), "warehouse", &Limits::default()).await.unwrap();
+    assert!(journal.runs[1].failed());
+
+    // The workspace still looks as it did before applying, so staging needs its clone again.
+    let mut journal = Journal::load(dir.path(), None).unwrap();
+    let error = journal.verify(&plan(&failing, &config).await.unwrap()).unwrap_err();
+    assert!(error.to_string().contains("staging now needs"), "{}", error);
+
+    // Once the workspace looks the way the first apply left it, only the rest is needed.
+    let mut fixture = Fixture::load("workspace");
+    partially_applied(&mut fixture);
+    let applied = MockWorkspace::serve(fixture).await;
+    let fresh = plan(&applied, &config).await.unwrap();
+    assert_eq!(statements(&fresh.runs[0]), Vec::<&str>::new());
+    assert_eq!(statements(&fresh.runs[1]), statements(&journal.runs[1])[2..]);
+    journal.verify(&fresh).unwrap();
+    // Nor can it resume operations that the workspace no longer needs.
+    let mut shorter = plan(&applied, &config).await.unwrap();
+    shorter.runs[1].queries.pop();
+    let error = journal.verify(&shorter).unwrap_err();
+    assert!(error.to_string().contains("dev no longer needs"), "{}", error);
+    let workspace = MockWorkspace::start("workspace").await;
+    journal.apply(workspace.crawler().backend.as_ref(), "warehouse", &Limits::default()).await.unwrap();
+    assert_eq!(workspace.statements(), statements(&journal.runs[1])[2..]);
+    assert!(journal.runs.iter().all(|run| !run.failed()));
+    assert!(Journal::load(dir.path(), None).is_err());
+}
+
+#[tokio::test]
+async fn a_statement_past_its_timeout_is_cancelled_and_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut fixture = Fixture::load("workspace");
+    fixture.keep_running("CREATE TABLE dev.sales.customers");
+    let workspace = MockWorkspace::serve(fixture).await;
+    let config = workspace.config(ENTRIES);
+    let mut journal = Journal::new(plan(&workspace, &config).await.unwrap(), dir.path()).unwrap();
+    let limits = Limits {
+        statement_timeout: Some(Duration::from_millis(200)),
+        ..Limits::default()
+    };
+    journal.apply(workspace.crawler().backend.as_ref(), "warehouse", &limits).await.unwrap();
+
+    let expected = [Status::Succeeded, Status::Succeeded, Status::Failed, Status::NotRun];
+    assert_eq!(statuses(&journal.runs[1]), expected);
+    let error = journal.runs[1].outcomes.iter().find_map(|o| o.error.as_deref()).unwrap();
+    assert!(error.contains("timed out"), "{}", error);
+    assert_eq!(workspace.cancelled(), ["statement-5"]);
+}
+
+#[tokio::test]
+async fn cancelling_stops_every_run_and_leaves_the_rest_to_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut fixture = Fixture::load("workspace");
+    fixture.keep_running("CREATE TABLE staging.sales.customers");
+    let workspace = MockWorkspace::serve(fixture).await;
+    let config = workspace.config(ENTRIES);
+    let mut journal = Journal::new(plan(&workspace, &config).await.unwrap(), dir.path()).unwrap();
+    let (cancel, cancelled) = tokio::sync::watch::channel(false);
+    let limits = Limits {
+        cancel: Some(cancelled),
+        ..Limits::default()
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel.send_replace(true);
+    });
+    journal.apply(workspace.crawler().backend.as_ref(), "warehouse", &limits).await.unwrap();
+
+    let [staging, dev] = &journal.runs[..] else { panic!("expected two runs") };
+    assert_eq!(statuses(staging), [Status::Cancelled]);
+    assert!(statuses(dev).iter().all(|status| *status == Status::NotRun));
+    assert_eq!(workspace.cancelled(), ["statement-1"]);
+    assert_eq!(workspace.statements().len(), 1);
+    assert!(!journal.completed());
+    assert_eq!(Journal::load(dir.path(), None).unwrap().plan_id, journal.plan_id);
+}